@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use serde_hkx_hkanno::{HkannoError, LoadedHkx};
+
+/// How many deserialized files are kept at most.
+///
+/// Class maps of large animations take several times their file size, so a
+/// batch dump of a whole mod must not pin all of them.
+const CAPACITY: usize = 32;
+
+/// HKX files deserialized by earlier commands, keyed by path.
+///
+/// Every lookup re-reads the file and reuses the entry only if the bytes are
+/// unchanged, so an edit within the file system's timestamp resolution is never
+/// missed; reading is cheap next to deserializing. The least recently used file
+/// is dropped once [`CAPACITY`] files are cached.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HkxCache {
+    entries: Arc<Mutex<Lru<Arc<LoadedHkx>>>>,
+}
+
+impl HkxCache {
+    /// Returns the cached file, loading it if it is missing or changed on disk.
+    pub(crate) async fn load(&self, path: &Path) -> Result<Arc<LoadedHkx>, HkannoError> {
+        let bytes = tokio::fs::read(path)
+            .await
+            .map_err(|source| HkannoError::IoError {
+                source,
+                path: path.to_path_buf(),
+            })?;
+
+        {
+            let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some(loaded) = entries.get(path) {
+                if loaded.bytes() == bytes.as_slice() {
+                    return Ok(Arc::clone(loaded));
+                }
+            }
+        }
+
+        let loaded = Arc::new(LoadedHkx::from_bytes(bytes, path)?);
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_path_buf(), Arc::clone(&loaded));
+        Ok(loaded)
+    }
+
+    /// Drops the cached file, e.g. after it was overwritten or closed.
+    pub(crate) fn forget(&self, path: &Path) {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(path);
+    }
+}
+
+/// Map from path to value that evicts the least recently used entry when full.
+#[derive(Debug)]
+struct Lru<V> {
+    capacity: usize,
+    /// Incremented on every access; an entry's stamp is its last access.
+    clock: u64,
+    entries: HashMap<PathBuf, (u64, V)>,
+}
+
+impl<V> Default for Lru<V> {
+    fn default() -> Self {
+        Self::with_capacity(CAPACITY)
+    }
+}
+
+impl<V> Lru<V> {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn get(&mut self, path: &Path) -> Option<&V> {
+        let now = self.tick();
+        self.entries.get_mut(path).map(|(used, value)| {
+            *used = now;
+            &*value
+        })
+    }
+
+    fn insert(&mut self, path: PathBuf, value: V) {
+        let now = self.tick();
+        self.entries.insert(path, (now, value));
+        while self.entries.len() > self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(path, _)| path.clone());
+            match oldest {
+                Some(oldest) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        self.entries.remove(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_evicts_the_least_recently_used_entry() {
+        let mut lru = Lru::with_capacity(2);
+        lru.insert("a".into(), 1);
+        lru.insert("b".into(), 2);
+        assert_eq!(lru.get(Path::new("a")), Some(&1));
+
+        lru.insert("c".into(), 3);
+        assert_eq!(lru.get(Path::new("b")), None);
+        assert_eq!(lru.get(Path::new("a")), Some(&1));
+        assert_eq!(lru.get(Path::new("c")), Some(&3));
+
+        lru.insert("a".into(), 4);
+        assert_eq!(lru.get(Path::new("a")), Some(&4));
+        assert_eq!(lru.entries.len(), 2);
+
+        lru.remove(Path::new("a"));
+        assert_eq!(lru.get(Path::new("a")), None);
+    }
+
+    #[tokio::test]
+    async fn missing_files_are_not_cached() {
+        let cache = HkxCache::default();
+        let path =
+            std::env::temp_dir().join(format!("hkanno_gui_missing_{}.hkx", std::process::id()));
+        let err = cache.load(&path).await.unwrap_err();
+        assert!(matches!(err, HkannoError::IoError { .. }), "{err:?}");
+        assert!(cache.entries.lock().unwrap().entries.is_empty());
+    }
+}
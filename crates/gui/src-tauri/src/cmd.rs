@@ -1,10 +1,19 @@
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use serde_hkx_hkanno::{editor::read_hkanno, file_collector::par_collect_hkx_files, HkannoError};
+use serde_hkx_hkanno::{
+    editor::{
+        apply_hkanno_loaded, diff_hkanno_changes_loaded, retime_hkanno_loaded, ApplyOptions,
+        ApplyOutcome, ApplyReport, SAME_AS_INPUT,
+    },
+    file_collector::{par_collect_hkx_files_with, CollectOptions},
+    is_valid_fps, AnnotationFilter, HkannoDiff, HkannoError, Retime,
+};
+use tauri::State;
 use tokio::task::JoinSet;
 
+use crate::cache::HkxCache;
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub(crate) struct AnnotationFile {
     /// Original HKX file path
@@ -17,20 +26,44 @@ pub(crate) struct AnnotationFile {
     content: String,
 }
 
+/// Annotations of the files that could be dumped, and why the others could not.
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct DumpResult {
+    files: Vec<AnnotationFile>,
+    /// Unreadable paths and files that failed to dump.
+    warnings: Vec<String>,
+}
+
+/// Dumps the annotations of every animation under `input`, timed at `fps`.
 #[tauri::command]
-pub(crate) async fn dump_annotations(input: Vec<PathBuf>) -> Result<Vec<AnnotationFile>, String> {
-    let hkx_files = par_collect_hkx_files(input).map_err(|e| e.to_string())?;
+pub(crate) async fn dump_annotations(
+    input: Vec<PathBuf>,
+    fps: f32,
+    cache: State<'_, HkxCache>,
+) -> Result<DumpResult, String> {
+    check_fps(fps)?;
+    // Skeletons and behaviors in mod folders have no annotations to dump.
+    let options = CollectOptions {
+        animations_only: true,
+        ..Default::default()
+    };
+    let collected = par_collect_hkx_files_with(input, &options).map_err(|e| e.to_string())?;
+    let mut errors: Vec<String> = collected.errors.iter().map(ToString::to_string).collect();
 
     let mut handles: JoinSet<Result<AnnotationFile, HkannoError>> = JoinSet::new();
 
-    for hkx_path in hkx_files {
+    for hkx_path in collected.files {
+        let cache = HkxCache::clone(&cache);
         handles.spawn(async move {
-            let content = read_hkanno(&hkx_path)
-                .await
-                .map_err(|e| HkannoError::HkxError {
-                    source: Box::new(e),
-                    path: hkx_path.clone(),
-                })?;
+            let content = async {
+                let loaded = cache.load(&hkx_path).await?;
+                loaded.extract(fps).map(|doc| doc.to_string())
+            }
+            .await
+            .map_err(|e| HkannoError::HkxError {
+                source: Box::new(e),
+                path: hkx_path.clone(),
+            })?;
             let anno_path = hkx_path.with_extension("txt"); // dummy
 
             let display_name = hkx_path
@@ -49,7 +82,6 @@ pub(crate) async fn dump_annotations(input: Vec<PathBuf>) -> Result<Vec<Annotati
     }
 
     let mut annotation_files = Vec::new();
-    let mut errors = Vec::new();
 
     while let Some(result) = handles.join_next().await {
         match result {
@@ -59,66 +91,79 @@ pub(crate) async fn dump_annotations(input: Vec<PathBuf>) -> Result<Vec<Annotati
         }
     }
 
-    let err_msg = if !errors.is_empty() {
-        let err_msg = errors.join("\n");
+    if !errors.is_empty() {
         #[cfg(feature = "tracing")]
-        tracing::error!("Errors during dump:\n{err_msg}");
-        err_msg
-    } else {
-        String::new()
-    };
-
-    if !annotation_files.is_empty() {
-        return Ok(annotation_files);
+        tracing::error!("Errors during dump:\n{}", errors.join("\n"));
     }
 
-    // When the user selects a directory, some HKX files lack animations.
-    // Therefore, to avoid a poor user experience caused by errors in a single file, we will only log the issue.
+    // When the user selects a directory, some paths may be unreadable or some HKX files broken.
+    // Therefore, to avoid a poor user experience caused by errors in a single file, open the rest
+    // and only report the failures as warnings.
     if annotation_files.is_empty() && !errors.is_empty() {
-        return Err(err_msg);
+        return Err(errors.join("\n"));
     }
 
-    Ok(annotation_files)
+    annotation_files.sort_by(|a, b| a.hkx_path.cmp(&b.hkx_path));
+    Ok(DumpResult {
+        files: annotation_files,
+        warnings: errors,
+    })
 }
 
+/// Saves each file's annotations; `format` may be [`SAME_AS_INPUT`] to keep every file's own format.
+///
+/// Returns a summary followed by one line per file with the format detected from its header.
 #[tauri::command]
 pub(crate) async fn update_annotations(
     files: Vec<AnnotationFile>,
     format: String,
+    cache: State<'_, HkxCache>,
 ) -> Result<String, String> {
-    let total_updated = Arc::new(AtomicUsize::new(0));
     let format = Arc::new(format);
 
-    let mut handles: JoinSet<Result<(), HkannoError>> = JoinSet::new();
+    let mut handles: JoinSet<Result<(String, ApplyReport), String>> = JoinSet::new();
     for AnnotationFile {
-        hkx_path, content, ..
+        hkx_path,
+        display_name,
+        content,
+        ..
     } in files
     {
-        let total_updated = Arc::clone(&total_updated);
         let format = Arc::clone(&format);
+        let cache = HkxCache::clone(&cache);
 
         handles.spawn(async move {
             let output_path = match format.as_str() {
+                format if format.eq_ignore_ascii_case(SAME_AS_INPUT) => hkx_path.clone(),
                 format if format.eq_ignore_ascii_case("xml") => hkx_path.with_extension("xml"),
                 _ => hkx_path.with_extension("hkx"),
             };
-            serde_hkx_hkanno::editor::apply_hkanno(
-                &hkx_path,
-                &output_path, // in-place update
-                &content,     // hkanno text
-                &format,
-            )
-            .await?;
-            total_updated.fetch_add(1, Ordering::Relaxed);
-            Ok(())
+            let report = async {
+                let loaded = cache.load(&hkx_path).await?;
+                apply_hkanno_loaded(
+                    &loaded,
+                    &output_path, // in-place update
+                    &content,     // hkanno text
+                    &format,
+                    &ApplyOptions::default(),
+                )
+                .await
+            }
+            .await
+            .map_err(|err| describe_error(&hkx_path, &content, err))?;
+            if report.outcome == ApplyOutcome::Written {
+                cache.forget(&output_path);
+            }
+            Ok((display_name, report))
         });
     }
 
+    let mut reports = Vec::new();
     let mut errors = Vec::new();
     while let Some(result) = handles.join_next().await {
         match result {
-            Ok(Ok(())) => (),
-            Ok(Err(err)) => errors.push(err.to_string()),
+            Ok(Ok(report)) => reports.push(report),
+            Ok(Err(err)) => errors.push(err),
             Err(err) => errors.push(err.to_string()),
         }
     }
@@ -130,8 +175,175 @@ pub(crate) async fn update_annotations(
         return Err(err_msg);
     }
 
-    Ok(format!(
-        "Updated {} file(s)",
-        total_updated.load(Ordering::Relaxed) + 1 // to 1 based index
-    ))
+    reports.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let total_updated = reports
+        .iter()
+        .filter(|(_, report)| report.outcome == ApplyOutcome::Written)
+        .count();
+    let mut message = format!(
+        "Updated {total_updated} file(s), {} unchanged",
+        reports.len() - total_updated
+    );
+    for (display_name, report) in &reports {
+        let detected = report
+            .input_format
+            .map_or_else(|| "unknown format".to_string(), |format| format.to_string());
+        let outcome = match report.outcome {
+            ApplyOutcome::Written => "written",
+            ApplyOutcome::Unchanged => "unchanged",
+        };
+        message.push_str(&format!("\n{display_name} ({detected}): {outcome}"));
+    }
+    Ok(message)
+}
+
+/// What saving one [`AnnotationFile`] would change.
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct AnnotationChanges {
+    hkx_path: PathBuf,
+    display_name: String,
+    /// Structured changes, for highlighting.
+    diff: HkannoDiff,
+    /// Unified-diff-like rendering of `diff` (empty if nothing changes).
+    unified: String,
+}
+
+/// Review step before `update_annotations`: diffs each file without writing anything.
+#[tauri::command]
+pub(crate) async fn review_annotation_changes(
+    files: Vec<AnnotationFile>,
+    fps: f32,
+    cache: State<'_, HkxCache>,
+) -> Result<Vec<AnnotationChanges>, String> {
+    check_fps(fps)?;
+    let mut handles: JoinSet<Result<AnnotationChanges, String>> = JoinSet::new();
+    for AnnotationFile {
+        hkx_path,
+        display_name,
+        content,
+        ..
+    } in files
+    {
+        let cache = HkxCache::clone(&cache);
+        handles.spawn(async move {
+            let diff = async {
+                let loaded = cache.load(&hkx_path).await?;
+                diff_hkanno_changes_loaded(&loaded, &content, fps)
+            }
+            .await
+            .map_err(|err| describe_error(&hkx_path, &content, err))?;
+            Ok(AnnotationChanges {
+                hkx_path,
+                display_name,
+                unified: diff.to_string(),
+                diff,
+            })
+        });
+    }
+
+    let mut changes = Vec::new();
+    let mut errors = Vec::new();
+    while let Some(result) = handles.join_next().await {
+        match result {
+            Ok(Ok(file)) => changes.push(file),
+            Ok(Err(err)) => errors.push(err),
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+
+    if !errors.is_empty() {
+        let err_msg = errors.join("\n");
+        #[cfg(feature = "tracing")]
+        tracing::error!("Errors during review annotation changes:\n{err_msg}");
+        return Err(err_msg);
+    }
+
+    changes.sort_by(|a, b| a.hkx_path.cmp(&b.hkx_path));
+    Ok(changes)
+}
+
+/// Changes the annotation times of each file's content (see [`Retime`]) without saving.
+///
+/// Only annotations of `tracks` whose text matches an `events` glob are changed;
+/// empty lists select everything. Returns the files with their new content.
+#[tauri::command]
+pub(crate) async fn retime_annotations(
+    files: Vec<AnnotationFile>,
+    retime: Retime,
+    tracks: Vec<String>,
+    events: Vec<String>,
+    fps: f32,
+    cache: State<'_, HkxCache>,
+) -> Result<Vec<AnnotationFile>, String> {
+    check_fps(fps)?;
+    let filter = Arc::new(AnnotationFilter::new(tracks, &events).map_err(|e| e.to_string())?);
+
+    let mut handles: JoinSet<Result<AnnotationFile, String>> = JoinSet::new();
+    for mut file in files {
+        let cache = HkxCache::clone(&cache);
+        let filter = Arc::clone(&filter);
+        handles.spawn(async move {
+            let retimed = async {
+                let loaded = cache.load(&file.hkx_path).await?;
+                retime_hkanno_loaded(&loaded, &file.content, retime, &filter, fps)
+            }
+            .await
+            .map_err(|err| describe_error(&file.hkx_path, &file.content, err))?;
+            if retimed.changed > 0 {
+                file.content = retimed.text;
+            }
+            Ok(file)
+        });
+    }
+
+    let mut retimed = Vec::new();
+    let mut errors = Vec::new();
+    while let Some(result) = handles.join_next().await {
+        match result {
+            Ok(Ok(file)) => retimed.push(file),
+            Ok(Err(err)) => errors.push(err),
+            Err(err) => errors.push(err.to_string()),
+        }
+    }
+
+    if !errors.is_empty() {
+        let err_msg = errors.join("\n");
+        #[cfg(feature = "tracing")]
+        tracing::error!("Errors during retime annotations:\n{err_msg}");
+        return Err(err_msg);
+    }
+
+    retimed.sort_by(|a, b| a.hkx_path.cmp(&b.hkx_path));
+    Ok(retimed)
+}
+
+/// Releases the cached HKX files of closed tabs.
+#[tauri::command]
+pub(crate) fn release_hkx_files(paths: Vec<PathBuf>, cache: State<'_, HkxCache>) {
+    for path in paths {
+        cache.forget(&path);
+    }
+}
+
+/// Rejects frame rates the CLI's `--fps` would reject too.
+fn check_fps(fps: f32) -> Result<(), String> {
+    if is_valid_fps(fps) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid frame rate {fps}: the frame rate must be a positive number"
+        ))
+    }
+}
+
+/// Formats an error for the UI, pointing hkanno syntax errors at `path:line:column`.
+fn describe_error(path: &Path, content: &str, err: HkannoError) -> String {
+    match err {
+        HkannoError::DeError { .. } => err.render(path, content),
+        err => HkannoError::HkxError {
+            source: Box::new(err),
+            path: path.to_path_buf(),
+        }
+        .to_string(),
+    }
 }
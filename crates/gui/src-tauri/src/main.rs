@@ -1,11 +1,13 @@
 // Prevents additional console window on Windows in release
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod cache;
 mod cmd;
 mod logger;
 
 fn main() {
     tauri::Builder::default()
+        .manage(crate::cache::HkxCache::default())
         .setup(|app| {
             #[cfg(feature = "tracing")]
             {
@@ -20,6 +22,9 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             crate::cmd::dump_annotations,
             crate::cmd::update_annotations,
+            crate::cmd::review_annotation_changes,
+            crate::cmd::retime_annotations,
+            crate::cmd::release_hkx_files,
             crate::logger::change_log_level,
         ])
         .run(tauri::generate_context!())
@@ -5,6 +5,9 @@ import { listen } from "@tauri-apps/api/event";
 import Editor from "@monaco-editor/react";
 import { supportHkanno } from "./support_hkanno";
 
+/** Frame rate used to convert annotation times to frames (matches the CLI's `--fps` default). */
+const DEFAULT_FPS = 30;
+
 interface Tab {
   hkxPath: string; // Original HKX file path
   annoPath: string; // Annotation .txt file path
@@ -33,7 +36,7 @@ interface SearchResult {
 function App() {
   const [tabs, setTabs] = useState<Tab[]>([]);
   const [activeTabId, setActiveTabId] = useState<string | null>(null);
-  const [format, setFormat] = useState<"amd64" | "win32" | "xml">("amd64");
+  const [format, setFormat] = useState<"same" | "amd64" | "win32" | "xml">("same");
   const [status, setStatus] = useState<StatusMessage>({ type: "idle", message: "Ready" });
   const [isDragging, setIsDragging] = useState(false);
   const tabsRef = useRef<Tab[]>([]);
@@ -95,18 +98,20 @@ function App() {
   const handleDump = async (paths: string[]) => {
     showStatus("loading", "Dumping annotations...");
     try {
-      const results = await invoke<
-        Array<{
+      const { files, warnings } = await invoke<{
+        files: Array<{
           hkx_path: string;
           anno_path: string;
           display_name: string;
           content: string;
-        }>
-      >("dump_annotations", {
+        }>;
+        warnings: string[];
+      }>("dump_annotations", {
         input: paths,
+        fps: DEFAULT_FPS,
       });
 
-      const newTabs: Tab[] = results.map((r) => ({
+      const newTabs: Tab[] = files.map((r) => ({
         hkxPath: r.hkx_path,
         annoPath: r.anno_path,
         displayName: r.display_name,
@@ -120,7 +125,8 @@ function App() {
         setActiveTabId(newTabs[0].annoPath);
       }
 
-      showStatus("success", `Dumped ${results.length} file(s)`);
+      const warningText = warnings.length > 0 ? `, ${warnings.length} warning(s):\n${warnings.join("\n")}` : "";
+      showStatus("success", `Dumped ${files.length} file(s)${warningText}`);
     } catch (error) {
       showStatus("error", `Dump failed: ${error}`);
     }
@@ -139,7 +145,7 @@ function App() {
 
     showStatus("loading", "Updating annotations...");
     try {
-      await invoke("update_annotations", {
+      const report = await invoke<string>("update_annotations", {
         files: [
           {
             hkx_path: activeTab.hkxPath,
@@ -153,7 +159,7 @@ function App() {
 
       setTabs((prev) => prev.map((tab) => (tab.annoPath === activeTabId ? { ...tab, modified: false } : tab)));
 
-      showStatus("success", "Saved successfully");
+      showStatus("success", report);
     } catch (error) {
       showStatus("error", `Save failed: ${error}`);
     }
@@ -165,7 +171,7 @@ function App() {
 
     showStatus("loading", `Saving ${modifiedTabs.length} file(s)...`);
     try {
-      await invoke("update_annotations", {
+      const report = await invoke<string>("update_annotations", {
         files: modifiedTabs.map((t) => ({
           hkx_path: t.hkxPath,
           anno_path: t.annoPath,
@@ -176,14 +182,18 @@ function App() {
       });
 
       setTabs((prev) => prev.map((tab) => ({ ...tab, modified: false })));
-      showStatus("success", `Saved ${modifiedTabs.length} file(s)`);
+      showStatus("success", report);
     } catch (error) {
       showStatus("error", `Bulk save failed: ${error}`);
     }
   };
 
   const handleCloseTab = (annoPath: string) => {
-    // No cleanup needed - files are in memory only
+    // Annotations are in memory only; just release the HKX cached by the backend.
+    const closed = tabs.find((t) => t.annoPath === annoPath);
+    if (closed) {
+      invoke("release_hkx_files", { paths: [closed.hkxPath] }).catch(() => {});
+    }
     setTabs((prev) => {
       const newTabs = prev.filter((t) => t.annoPath !== annoPath);
       if (activeTabId === annoPath && newTabs.length > 0) {
@@ -360,8 +370,9 @@ function App() {
         <select
           className="format-select"
           value={format}
-          onChange={(e) => setFormat(e.target.value as "amd64" | "win32" | "xml")}
+          onChange={(e) => setFormat(e.target.value as "same" | "amd64" | "win32" | "xml")}
         >
+          <option value="same">Same as input</option>
           <option value="amd64">64-bit (SE/AE)</option>
           <option value="win32">32-bit (LE)</option>
           <option value="xml">XML</option>
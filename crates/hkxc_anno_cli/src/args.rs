@@ -0,0 +1,399 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_hkx_hkanno::{
+    editor::{BackupPolicy, SAME_AS_INPUT},
+    file_collector::CollectOptions,
+    is_valid_fps, Retime, DEFAULT_FPS,
+};
+
+/// Dump, apply, preview and validate the annotations of HKX animations.
+///
+/// Annotations are exchanged as hkanno text in `<name>.txt` next to each HKX file.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Command,
+
+    /// Level of the log messages printed to stderr (error, warn, info, debug, trace).
+    #[arg(long, global = true, default_value = "warn")]
+    pub(crate) log_level: tracing::Level,
+
+    /// How results are printed to stdout.
+    #[arg(long, global = true, value_enum, default_value_t = MessageFormat::Text)]
+    pub(crate) message_format: MessageFormat,
+}
+
+/// `--message-format` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum MessageFormat {
+    /// Human-readable lines; failures go to stderr.
+    Text,
+    /// One JSON object holding every result.
+    Json,
+    /// One JSON object per file and line (JSON Lines).
+    JsonLines,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Write the annotations of each HKX file to `<name>.txt`.
+    Dump(DumpArgs),
+    /// Write each `<name>.txt` back into its HKX file.
+    Apply(ApplyArgs),
+    /// Print or write the XML each HKX file would have with its `<name>.txt` applied.
+    PreviewXml(PreviewXmlArgs),
+    /// Check each `<name>.txt` against its HKX file without writing anything.
+    Validate(ValidateArgs),
+    /// Write every annotation of the HKX files to one CSV/TSV table.
+    ExportTable(ExportTableArgs),
+    /// Write the annotations of a table from `export-table` back into the listed files.
+    ///
+    /// The rows of a file replace all its annotations. Files changed since the
+    /// export are reported and left untouched.
+    ImportTable(ImportTableArgs),
+    /// Scale, remap, shift or snap the annotation times in each `<name>.txt`.
+    ///
+    /// Sections take the duration of their animation in the HKX file, so `--remap-from`
+    /// maps onto the file's actual length and `--shift` clamps to it. Only the changed
+    /// times are rewritten; comments, blank lines and `f12`/`12f` frame times are kept.
+    Retime(RetimeArgs),
+}
+
+/// Which files to process.
+#[derive(Debug, Args)]
+pub(crate) struct CollectArgs {
+    /// HKX/XML files, or directories to search recursively.
+    #[arg(required = true)]
+    pub(crate) inputs: Vec<PathBuf>,
+
+    /// How many directory levels to descend below an input directory (0: only its own files).
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Only collect files matching this glob, relative to the input directory (repeatable).
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files and directories matching this glob (repeatable).
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Ignore `.hkannoignore` files.
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// Do not follow symlinks.
+    #[arg(long)]
+    no_follow_symlinks: bool,
+
+    /// Skip files without an `hkaAnimation` class, such as skeletons and behaviors.
+    #[arg(long)]
+    animations_only: bool,
+}
+
+impl CollectArgs {
+    pub(crate) fn options(&self) -> CollectOptions {
+        CollectOptions {
+            max_depth: self.max_depth,
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            ignore_files: !self.no_ignore,
+            follow_symlinks: !self.no_follow_symlinks,
+            animations_only: self.animations_only,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct DumpArgs {
+    #[command(flatten)]
+    pub(crate) collect: CollectArgs,
+
+    /// Frame rate used to derive the frame count of non-spline animations.
+    #[arg(long, default_value_t = DEFAULT_FPS, value_parser = parse_fps)]
+    pub(crate) fps: f32,
+
+    /// Overwrite existing `<name>.txt` files.
+    #[arg(long)]
+    pub(crate) force: bool,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ApplyArgs {
+    #[command(flatten)]
+    pub(crate) collect: CollectArgs,
+
+    /// Output format: `same` (keep each file's format), `amd64`, `win32` or `xml`.
+    ///
+    /// `amd64`/`win32` write `<name>.hkx`, `xml` writes `<name>.xml`.
+    #[arg(long, default_value = SAME_AS_INPUT)]
+    pub(crate) format: String,
+
+    #[command(flatten)]
+    pub(crate) write: WriteArgs,
+
+    /// Print what would change instead of writing.
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+}
+
+/// How annotations are written into HKX files.
+#[derive(Debug, Args)]
+pub(crate) struct WriteArgs {
+    #[command(flatten)]
+    pub(crate) check: CheckArgs,
+
+    /// Refuse to write a file whose annotations have error-level issues.
+    #[arg(long)]
+    pub(crate) strict: bool,
+
+    /// Deserialize each output again and refuse to write it unless it round-trips.
+    ///
+    /// Only with `--format same`: converting formats never round-trips exactly.
+    #[arg(long)]
+    pub(crate) verify: bool,
+
+    /// How to back up a file before replacing it.
+    #[arg(long, value_enum, default_value_t = Backup::None)]
+    pub(crate) backup: Backup,
+
+    /// Put backups into this directory instead (implies timestamped backups).
+    #[arg(long, value_name = "DIR")]
+    pub(crate) backup_dir: Option<PathBuf>,
+
+    /// Backups to keep per file in `--backup-dir` (0: all).
+    #[arg(long, default_value_t = 0, requires = "backup_dir")]
+    pub(crate) keep: usize,
+}
+
+impl WriteArgs {
+    pub(crate) fn backup_policy(&self) -> BackupPolicy {
+        match (&self.backup_dir, self.backup) {
+            (Some(dir), _) => BackupPolicy::Directory {
+                dir: dir.clone(),
+                keep: self.keep,
+            },
+            (None, Backup::None) => BackupPolicy::None,
+            (None, Backup::Sibling) => BackupPolicy::Sibling,
+            (None, Backup::Timestamped) => BackupPolicy::Timestamped,
+        }
+    }
+}
+
+/// `--backup` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Backup {
+    /// Keep no backup.
+    None,
+    /// Copy to `<file>.bak`, replacing the previous backup.
+    Sibling,
+    /// Copy to `<file>.<timestamp>.bak`, keeping every backup.
+    Timestamped,
+}
+
+/// How annotations are checked against the animation.
+#[derive(Debug, Args)]
+pub(crate) struct CheckArgs {
+    /// Frame rate used to derive the frame count of non-spline animations
+    /// (and to read the `frame` column of `import-table`).
+    #[arg(long, default_value_t = DEFAULT_FPS, value_parser = parse_fps)]
+    pub(crate) fps: f32,
+
+    /// Extra PIE instruction definitions (`.toml` or `.json`) to check payloads against.
+    #[arg(long, value_name = "FILE")]
+    pub(crate) pie: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct PreviewXmlArgs {
+    #[command(flatten)]
+    pub(crate) collect: CollectArgs,
+
+    /// Write `<DIR>/<name>.xml` for each file instead of printing the XML.
+    ///
+    /// Text output can only print a single file.
+    #[arg(long, value_name = "DIR")]
+    pub(crate) out_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ExportTableArgs {
+    #[command(flatten)]
+    pub(crate) collect: CollectArgs,
+
+    /// Table to write; `.tsv` or `.tab` writes tab-separated values, anything else CSV.
+    #[arg(short, long, value_name = "FILE")]
+    pub(crate) output: PathBuf,
+
+    /// Frame rate of the `frame` column.
+    #[arg(long, default_value_t = DEFAULT_FPS, value_parser = parse_fps)]
+    pub(crate) fps: f32,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ImportTableArgs {
+    /// Table written by `export-table`, possibly edited.
+    ///
+    /// File paths in it are resolved against the working directory, like on export.
+    pub(crate) table: PathBuf,
+
+    #[command(flatten)]
+    pub(crate) write: WriteArgs,
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct RetimeArgs {
+    #[command(flatten)]
+    pub(crate) collect: CollectArgs,
+
+    #[command(flatten)]
+    pub(crate) operation: RetimeOperation,
+
+    /// Frame rate of `--snap`, also used to derive the frame count of non-spline animations.
+    #[arg(long, default_value_t = DEFAULT_FPS, value_parser = parse_fps)]
+    pub(crate) fps: f32,
+
+    /// Only change annotations of this track (repeatable).
+    #[arg(long, value_name = "NAME")]
+    pub(crate) track: Vec<String>,
+
+    /// Only change annotations whose text matches this glob, e.g. `SoundPlay.*` (repeatable).
+    #[arg(long, value_name = "GLOB")]
+    pub(crate) event: Vec<String>,
+
+    /// Print what would change instead of writing.
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+}
+
+/// The change `retime` makes; exactly one is required.
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+pub(crate) struct RetimeOperation {
+    /// Multiply every time by this factor.
+    #[arg(long, value_name = "FACTOR")]
+    scale: Option<f32>,
+
+    /// Map times from an animation of this many seconds onto the file's duration.
+    #[arg(long, value_name = "SECONDS")]
+    remap_from: Option<f32>,
+
+    /// Add this many seconds (negative: earlier), clamped to the file's duration.
+    #[arg(long, value_name = "SECONDS", allow_negative_numbers = true)]
+    shift: Option<f32>,
+
+    /// Round every time to the nearest frame at `--fps`.
+    #[arg(long)]
+    snap: bool,
+}
+
+impl RetimeOperation {
+    pub(crate) fn retime(&self, fps: f32) -> Retime {
+        match (self.scale, self.remap_from, self.shift) {
+            (Some(factor), ..) => Retime::Scale { factor },
+            (_, Some(from_duration), _) => Retime::Remap { from_duration },
+            (.., Some(offset)) => Retime::Shift { offset },
+            // clap requires one of the group.
+            _ => Retime::Snap { fps },
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct ValidateArgs {
+    #[command(flatten)]
+    pub(crate) collect: CollectArgs,
+
+    #[command(flatten)]
+    pub(crate) check: CheckArgs,
+
+    /// Treat warnings as errors for the exit status.
+    #[arg(long)]
+    pub(crate) deny_warnings: bool,
+}
+
+/// Parses `--fps`, which must be a finite, positive number.
+fn parse_fps(value: &str) -> Result<f32, String> {
+    let fps: f32 = value
+        .parse()
+        .map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    match is_valid_fps(fps) {
+        true => Ok(fps),
+        false => Err("the frame rate must be a positive number".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory as _;
+
+    #[test]
+    fn cli_is_consistent() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn backup_dir_overrides_backup() {
+        let cli = Cli::parse_from([
+            "hkxc-anno-cli",
+            "apply",
+            "meshes",
+            "--backup",
+            "sibling",
+            "--backup-dir",
+            "backups",
+            "--keep",
+            "3",
+        ]);
+        let Command::Apply(args) = cli.command else {
+            panic!("expected apply");
+        };
+        assert_eq!(args.format, SAME_AS_INPUT);
+        assert_eq!(
+            args.write.backup_policy(),
+            BackupPolicy::Directory {
+                dir: PathBuf::from("backups"),
+                keep: 3
+            }
+        );
+    }
+
+    #[test]
+    fn retime_takes_one_operation() {
+        let cli = Cli::parse_from(["hkxc-anno-cli", "retime", "idle.hkx", "--shift", "-0.25"]);
+        let Command::Retime(args) = cli.command else {
+            panic!("expected retime");
+        };
+        assert_eq!(
+            args.operation.retime(args.fps),
+            Retime::Shift { offset: -0.25 }
+        );
+
+        let both = [
+            "hkxc-anno-cli",
+            "retime",
+            "idle.hkx",
+            "--scale",
+            "2",
+            "--snap",
+        ];
+        assert!(Cli::try_parse_from(both).is_err());
+        assert!(Cli::try_parse_from(["hkxc-anno-cli", "retime", "idle.hkx"]).is_err());
+    }
+
+    #[test]
+    fn fps_must_be_positive() {
+        for fps in ["0", "-30", "inf", "NaN", "fast"] {
+            let args = ["hkxc-anno-cli", "dump", "meshes", "--fps", fps];
+            assert!(Cli::try_parse_from(args).is_err(), "--fps {fps}");
+        }
+        let cli = Cli::parse_from(["hkxc-anno-cli", "dump", "meshes", "--fps", "60"]);
+        let Command::Dump(args) = cli.command else {
+            panic!("expected dump");
+        };
+        assert_eq!(args.fps, 60.0);
+    }
+}
@@ -0,0 +1,671 @@
+//! Subcommands.
+//!
+//! Each subcommand collects the input files, processes them concurrently and turns
+//! the result of every file into a [`Record`], printed once all files are done (see
+//! [`crate::output`]). A failing file never stops the others; it only changes the
+//! exit [`Status`].
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde_hkx_hkanno::{
+    diff_documents,
+    editor::{
+        apply_hkanno, diff_hkanno_changes, hkanno_apply_xml_string, retime_hkanno, ApplyOptions,
+        Retimed, SAME_AS_INPUT,
+    },
+    file_collector::par_collect_hkx_files_with,
+    parse_hkanno_document_str, parse_hkanno_document_str_recovering,
+    report::{Finding, Record},
+    table::{self, TableFormat, TableImport},
+    AnnotationFilter, HkannoError, InstructionRegistry, LoadedHkx, Severity, ValidationMode,
+    ValidationOptions,
+};
+use tokio::{fs, task::JoinSet};
+
+use crate::args::{
+    ApplyArgs, CheckArgs, CollectArgs, DumpArgs, ExportTableArgs, ImportTableArgs, MessageFormat,
+    PreviewXmlArgs, RetimeArgs, ValidateArgs, WriteArgs,
+};
+use crate::output::emit;
+use crate::status::Status;
+
+/// Writes `<name>.txt` next to every file.
+pub(crate) async fn dump(args: DumpArgs, format: MessageFormat) -> Status {
+    let Collected { files, mut records } = match collect(&args.collect, format) {
+        Ok(collected) => collected,
+        Err(status) => return status,
+    };
+    if let Some(message) = find_collision(&files, annotation_path) {
+        return usage_error(message, format);
+    }
+
+    let (fps, force) = (args.fps, args.force);
+    records.extend(
+        run_each(files, move |hkx_path| async move {
+            let annotation_path = annotation_path(&hkx_path);
+            let document = LoadedHkx::open(&hkx_path)
+                .await
+                .and_then(|loaded| loaded.extract(fps))
+                .map_err(|e| e.to_string())?;
+
+            let written = force || !fs::try_exists(&annotation_path).await.unwrap_or(false);
+            if written {
+                fs::write(&annotation_path, document.to_string())
+                    .await
+                    .map_err(|e| format!("failed to write {}: {e}", annotation_path.display()))?;
+            }
+            Ok(Record::Dump {
+                path: hkx_path,
+                annotation_path,
+                written,
+                document,
+            })
+        })
+        .await,
+    );
+
+    emit(&records, format);
+    failure_status(&records)
+}
+
+/// Writes every `<name>.txt` into its file.
+pub(crate) async fn apply(args: ApplyArgs, format: MessageFormat) -> Status {
+    let Collected { files, mut records } = match collect(&args.collect, format) {
+        Ok(collected) => collected,
+        Err(status) => return status,
+    };
+    if args.write.verify && !args.format.eq_ignore_ascii_case(SAME_AS_INPUT) {
+        let message = format!(
+            "--verify only works with --format {SAME_AS_INPUT}: converted output does not round-trip exactly"
+        );
+        return usage_error(message, format);
+    }
+    let collision = find_collision(&files, annotation_path).or_else(|| match args.dry_run {
+        true => None,
+        false => find_collision(&files, |path| output_path(path, &args.format)),
+    });
+    if let Some(message) = collision {
+        return usage_error(message, format);
+    }
+    let options = match apply_options(&args.write) {
+        Ok(options) => Arc::new(options),
+        Err(err) => return usage_error(err.to_string(), format),
+    };
+    let out_format = Arc::new(args.format);
+    let (fps, dry_run) = (args.write.check.fps, args.dry_run);
+
+    records.extend(
+        run_each(files, move |hkx_path| {
+            let options = Arc::clone(&options);
+            let out_format = Arc::clone(&out_format);
+            async move {
+                let anno_path = annotation_path(&hkx_path);
+                let Some(content) = read_annotations(&anno_path).await? else {
+                    return Ok(skipped_without(hkx_path, &anno_path));
+                };
+
+                if dry_run {
+                    let diff = diff_hkanno_changes(&hkx_path, &content, fps)
+                        .await
+                        .map_err(|err| err.render(&anno_path, &content))?;
+                    return Ok(Record::Diff {
+                        path: hkx_path,
+                        diff,
+                    });
+                }
+
+                let output = output_path(&hkx_path, &out_format);
+                let report = apply_hkanno(&hkx_path, &output, &content, &out_format, &options)
+                    .await
+                    .map_err(|err| err.render(&anno_path, &content))?;
+                Ok(Record::Apply {
+                    path: hkx_path,
+                    output,
+                    outcome: report.outcome,
+                    input_format: report.input_format,
+                })
+            }
+        })
+        .await,
+    );
+
+    emit(&records, format);
+    failure_status(&records)
+}
+
+/// Writes `<out_dir>/<name>.xml` for each file, or prints the XML.
+pub(crate) async fn preview_xml(args: PreviewXmlArgs, format: MessageFormat) -> Status {
+    let Collected { files, mut records } = match collect(&args.collect, format) {
+        Ok(collected) => collected,
+        Err(status) => return status,
+    };
+
+    let out_dir = args.out_dir.map(Arc::new);
+    match &out_dir {
+        // JSON output keeps the XML of each file apart; plain stdout cannot.
+        None if files.len() > 1 && format == MessageFormat::Text => {
+            let message = format!(
+                "{} files found; use --out-dir to preview more than one file",
+                files.len()
+            );
+            return usage_error(message, format);
+        }
+        Some(out_dir) => {
+            if let Some(message) = find_collision(&files, |path| preview_path(out_dir, path)) {
+                return usage_error(message, format);
+            }
+        }
+        None => {}
+    }
+
+    records.extend(
+        run_each(files, move |hkx_path| {
+            let out_dir = out_dir.clone();
+            async move {
+                let anno_path = annotation_path(&hkx_path);
+                let Some(content) = read_annotations(&anno_path).await? else {
+                    return Ok(skipped_without(hkx_path, &anno_path));
+                };
+                let xml = hkanno_apply_xml_string(&hkx_path, &content)
+                    .await
+                    .map_err(|err| err.render(&anno_path, &content))?;
+
+                let Some(out_dir) = out_dir else {
+                    return Ok(Record::Preview {
+                        path: hkx_path,
+                        output: None,
+                        xml: Some(xml),
+                    });
+                };
+                let preview = preview_path(&out_dir, &hkx_path);
+                let write = async {
+                    fs::create_dir_all(&*out_dir).await?;
+                    fs::write(&preview, xml).await
+                };
+                write
+                    .await
+                    .map_err(|e| format!("failed to write {}: {e}", preview.display()))?;
+                Ok(Record::Preview {
+                    path: hkx_path,
+                    output: Some(preview),
+                    xml: None,
+                })
+            }
+        })
+        .await,
+    );
+
+    emit(&records, format);
+    failure_status(&records)
+}
+
+/// Checks every `<name>.txt` against its file.
+pub(crate) async fn validate(args: ValidateArgs, format: MessageFormat) -> Status {
+    let Collected { files, mut records } = match collect(&args.collect, format) {
+        Ok(collected) => collected,
+        Err(status) => return status,
+    };
+    let validation = match validation_options(&args.check) {
+        Ok(validation) => Arc::new(validation),
+        Err(err) => return usage_error(err.to_string(), format),
+    };
+
+    records.extend(
+        run_each(files, move |hkx_path| {
+            let validation = Arc::clone(&validation);
+            async move {
+                let annotation_path = annotation_path(&hkx_path);
+                let Some(content) = read_annotations(&annotation_path).await? else {
+                    return Ok(skipped_without(hkx_path, &annotation_path));
+                };
+
+                let (syntax_errors, findings) = match parse_hkanno_document_str(&content) {
+                    Ok(document) => {
+                        let loaded = LoadedHkx::open(&hkx_path)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                        let issues = loaded
+                            .validate(&document, &validation)
+                            .map_err(|e| e.to_string())?;
+                        let findings = issues
+                            .into_iter()
+                            .map(|(ptr, issue)| Finding::new(ptr, issue))
+                            .collect();
+                        (Vec::new(), findings)
+                    }
+                    Err(err) => {
+                        let (_, errors) = parse_hkanno_document_str_recovering(&content);
+                        match errors.is_empty() {
+                            true => (vec![err], Vec::new()),
+                            false => (errors, Vec::new()),
+                        }
+                    }
+                };
+                Ok(Record::Validation {
+                    path: hkx_path,
+                    annotation_path,
+                    syntax_errors,
+                    findings,
+                })
+            }
+        })
+        .await,
+    );
+
+    emit(&records, format);
+
+    let (mut errors, mut warnings) = (0, 0);
+    for record in &records {
+        if let Record::Validation {
+            syntax_errors,
+            findings,
+            ..
+        } = record
+        {
+            errors += syntax_errors.len();
+            for finding in findings {
+                match finding.severity {
+                    Severity::Error => errors += 1,
+                    Severity::Warning => warnings += 1,
+                }
+            }
+        }
+    }
+    if format == MessageFormat::Text {
+        eprintln!("{errors} error(s), {warnings} warning(s)");
+    }
+
+    match failure_status(&records) {
+        Status::Success if errors > 0 || (args.deny_warnings && warnings > 0) => Status::Invalid,
+        status => status,
+    }
+}
+
+/// Writes the annotations of every file to one table.
+pub(crate) async fn export_table(args: ExportTableArgs, format: MessageFormat) -> Status {
+    let Collected { files, mut records } = match collect(&args.collect, format) {
+        Ok(collected) => collected,
+        Err(status) => return status,
+    };
+
+    let table = args.output;
+    let writer = match std::fs::File::create(&table) {
+        Ok(file) => io::BufWriter::new(file),
+        Err(err) => {
+            let message = format!("failed to create {}: {err}", table.display());
+            records.push(Record::Error {
+                path: Some(table),
+                message,
+            });
+            emit(&records, format);
+            return Status::Failure;
+        }
+    };
+
+    let table_format = TableFormat::from_path(&table).unwrap_or_default();
+    let fps = args.fps;
+    let exported =
+        tokio::task::spawn_blocking(move || table::export_table(&files, fps, table_format, writer))
+            .await;
+    match exported {
+        Ok(Ok(export)) => {
+            records.extend(export.files.into_iter().map(|(path, rows)| match rows {
+                Ok(rows) => Record::Export {
+                    path,
+                    table: table.clone(),
+                    rows,
+                },
+                Err(err) => Record::Error {
+                    path: Some(path),
+                    message: err.to_string(),
+                },
+            }));
+        }
+        Ok(Err(err)) => records.push(Record::Error {
+            path: Some(table),
+            message: err.to_string(),
+        }),
+        Err(join_err) => records.push(Record::Error {
+            path: None,
+            message: format!("Task panicked: {join_err}"),
+        }),
+    }
+
+    emit(&records, format);
+    failure_status(&records)
+}
+
+/// Writes the rows of a table into the files they list.
+pub(crate) async fn import_table(args: ImportTableArgs, format: MessageFormat) -> Status {
+    let options = match apply_options(&args.write) {
+        Ok(options) => options,
+        Err(err) => return usage_error(err.to_string(), format),
+    };
+
+    let table = args.table;
+    let imported = match std::fs::File::open(&table) {
+        Ok(file) => {
+            let table_format = TableFormat::from_path(&table).unwrap_or_default();
+            let fps = args.write.check.fps;
+            let reader = io::BufReader::new(file);
+            table::import_table(reader, table_format, fps, SAME_AS_INPUT, &options)
+                .await
+                .map_err(|err| err.to_string())
+        }
+        Err(err) => Err(format!("failed to open {}: {err}", table.display())),
+    };
+
+    let records: Vec<_> = match imported {
+        Ok(imports) => imports
+            .into_iter()
+            .map(|TableImport { path, result }| match result {
+                Ok(report) => Record::Apply {
+                    output: path.clone(),
+                    path,
+                    outcome: report.outcome,
+                    input_format: report.input_format,
+                },
+                Err(err) => Record::Error {
+                    path: Some(path),
+                    message: err.to_string(),
+                },
+            })
+            .collect(),
+        Err(message) => vec![Record::Error {
+            path: Some(table),
+            message,
+        }],
+    };
+
+    emit(&records, format);
+    failure_status(&records)
+}
+
+/// Changes the annotation times in every `<name>.txt`.
+pub(crate) async fn retime(args: RetimeArgs, format: MessageFormat) -> Status {
+    let Collected { files, mut records } = match collect(&args.collect, format) {
+        Ok(collected) => collected,
+        Err(status) => return status,
+    };
+    if let Some(message) = find_collision(&files, annotation_path) {
+        return usage_error(message, format);
+    }
+    let filter = match AnnotationFilter::new(args.track, &args.event) {
+        Ok(filter) => Arc::new(filter),
+        Err(err) => return usage_error(err.to_string(), format),
+    };
+    let (fps, dry_run) = (args.fps, args.dry_run);
+    let operation = args.operation.retime(fps);
+
+    records.extend(
+        run_each(files, move |hkx_path| {
+            let filter = Arc::clone(&filter);
+            async move {
+                let annotation_path = annotation_path(&hkx_path);
+                let Some(content) = read_annotations(&annotation_path).await? else {
+                    return Ok(skipped_without(hkx_path, &annotation_path));
+                };
+                let Retimed {
+                    document,
+                    text,
+                    changed,
+                } = retime_hkanno(&hkx_path, &content, operation, &filter, fps)
+                    .await
+                    .map_err(|err| err.render(&annotation_path, &content))?;
+
+                if dry_run {
+                    let original =
+                        parse_hkanno_document_str(&content).map_err(|e| e.to_string())?;
+                    return Ok(Record::Diff {
+                        path: hkx_path,
+                        diff: diff_documents(&original, &document).map_err(|e| e.to_string())?,
+                    });
+                }
+                if changed > 0 {
+                    fs::write(&annotation_path, text).await.map_err(|e| {
+                        format!("failed to write {}: {e}", annotation_path.display())
+                    })?;
+                }
+                Ok(Record::Retime {
+                    path: hkx_path,
+                    annotation_path,
+                    changed,
+                })
+            }
+        })
+        .await,
+    );
+
+    emit(&records, format);
+    failure_status(&records)
+}
+
+/// Files to process, and the records of the input paths that could not be read.
+struct Collected {
+    files: Vec<PathBuf>,
+    records: Vec<Record<'static>>,
+}
+
+/// Collects the input files.
+///
+/// Returns the status to exit with if there is nothing to process.
+fn collect(args: &CollectArgs, format: MessageFormat) -> Result<Collected, Status> {
+    let outcome = match par_collect_hkx_files_with(args.inputs.clone(), &args.options()) {
+        Ok(outcome) => outcome,
+        Err(err) => return Err(usage_error(err.to_string(), format)),
+    };
+
+    let records: Vec<_> = outcome
+        .errors
+        .iter()
+        .map(|err| Record::Error {
+            path: err.path().map(Path::to_path_buf),
+            message: err.to_string(),
+        })
+        .collect();
+
+    if outcome.files.is_empty() {
+        if records.is_empty() {
+            let record = Record::Error {
+                path: None,
+                message: "no HKX files found".to_string(),
+            };
+            emit(&[record], format);
+            return Err(Status::NoInput);
+        }
+        emit(&records, format);
+        return Err(Status::Failure);
+    }
+    Ok(Collected {
+        files: outcome.files,
+        records,
+    })
+}
+
+/// Describes the first two files whose `target` paths are the same, e.g. `a.hkx`
+/// and `a.xml`, which share `a.txt`.
+fn find_collision(files: &[PathBuf], target: impl Fn(&Path) -> PathBuf) -> Option<String> {
+    let mut targets = HashMap::new();
+    files.iter().find_map(|path| {
+        let target = target(path);
+        let earlier = targets.insert(target.clone(), path)?;
+        Some(format!(
+            "{} and {} would both use {}; process them separately",
+            earlier.display(),
+            path.display(),
+            target.display()
+        ))
+    })
+}
+
+/// Prints `message` as an error record and returns [`Status::Usage`].
+fn usage_error(message: String, format: MessageFormat) -> Status {
+    emit(
+        &[Record::Error {
+            path: None,
+            message,
+        }],
+        format,
+    );
+    Status::Usage
+}
+
+/// [`Status::Failure`] if any record is an error.
+fn failure_status(records: &[Record<'_>]) -> Status {
+    match records
+        .iter()
+        .any(|record| matches!(record, Record::Error { .. }))
+    {
+        true => Status::Failure,
+        false => Status::Success,
+    }
+}
+
+/// Runs `task` on every file concurrently and returns the records sorted by path.
+///
+/// An `Err` of `task` becomes a [`Record::Error`] of its file.
+async fn run_each<F, Fut>(files: Vec<PathBuf>, task: F) -> Vec<Record<'static>>
+where
+    F: Fn(PathBuf) -> Fut,
+    Fut: Future<Output = Result<Record<'static>, String>> + Send + 'static,
+{
+    let mut handles = JoinSet::new();
+    for path in files {
+        let future = task(path.clone());
+        handles.spawn(async move { (path, future.await) });
+    }
+
+    let mut results = Vec::new();
+    let mut panics = Vec::new();
+    while let Some(result) = handles.join_next().await {
+        match result {
+            Ok(result) => results.push(result),
+            Err(join_err) => panics.push(Record::Error {
+                path: None,
+                message: format!("Task panicked: {join_err}"),
+            }),
+        }
+    }
+    results.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    results
+        .into_iter()
+        .map(|(path, result)| {
+            result.unwrap_or_else(|message| Record::Error {
+                path: Some(path),
+                message,
+            })
+        })
+        .chain(panics)
+        .collect()
+}
+
+/// Path of the hkanno text of an HKX file: `<name>.txt` in the same directory.
+fn annotation_path(hkx_path: &Path) -> PathBuf {
+    hkx_path.with_extension("txt")
+}
+
+/// Output path of an HKX file saved in `format`; `same` updates it in place.
+fn output_path(hkx_path: &Path, format: &str) -> PathBuf {
+    match format {
+        format if format.eq_ignore_ascii_case("xml") => hkx_path.with_extension("xml"),
+        format if format.eq_ignore_ascii_case("amd64") || format.eq_ignore_ascii_case("win32") => {
+            hkx_path.with_extension("hkx")
+        }
+        _ => hkx_path.to_path_buf(),
+    }
+}
+
+/// Path of the preview of an HKX file: `<out_dir>/<name>.xml`.
+fn preview_path(out_dir: &Path, hkx_path: &Path) -> PathBuf {
+    let mut name = hkx_path.file_stem().unwrap_or_default().to_os_string();
+    name.push(".xml");
+    out_dir.join(name)
+}
+
+fn skipped_without(hkx_path: PathBuf, anno_path: &Path) -> Record<'static> {
+    Record::Skipped {
+        path: hkx_path,
+        reason: format!("no {}", anno_path.display()),
+    }
+}
+
+/// Reads an annotation file, returning `None` if it does not exist.
+async fn read_annotations(anno_path: &Path) -> Result<Option<String>, String> {
+    match fs::read_to_string(anno_path).await {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(format!("failed to read {}: {err}", anno_path.display())),
+    }
+}
+
+/// Builds the options of writing annotations, with `--strict` validation if requested.
+fn apply_options(args: &WriteArgs) -> Result<ApplyOptions, HkannoError> {
+    let mut validation = validation_options(&args.check)?;
+    if args.strict {
+        validation.mode = ValidationMode::Strict;
+    }
+    Ok(ApplyOptions {
+        validation,
+        backup: args.backup_policy(),
+        verify: args.verify,
+    })
+}
+
+/// Builds the validation options, adding `--pie` definitions to the built-in ones.
+fn validation_options(args: &CheckArgs) -> Result<ValidationOptions, HkannoError> {
+    let mut validation = ValidationOptions {
+        fps: args.fps,
+        ..Default::default()
+    };
+    if let Some(path) = &args.pie {
+        let mut registry = InstructionRegistry::builtin().as_ref().clone();
+        registry.extend_from_file(path)?;
+        validation.pie = Some(Arc::new(registry));
+    }
+    Ok(validation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_paths_follow_format() {
+        let hkx = Path::new("meshes/actors/character/animations/idle.hkx");
+        assert_eq!(output_path(hkx, "same"), hkx);
+        assert_eq!(output_path(hkx, "XML"), hkx.with_extension("xml"));
+        assert_eq!(
+            output_path(&hkx.with_extension("xml"), "win32"),
+            hkx.with_extension("hkx")
+        );
+        assert_eq!(annotation_path(hkx), hkx.with_extension("txt"));
+        assert_eq!(
+            preview_path(Path::new("out"), hkx),
+            Path::new("out/idle.xml")
+        );
+    }
+
+    #[test]
+    fn collisions_name_both_files() {
+        let files = [
+            PathBuf::from("a/idle.hkx"),
+            PathBuf::from("a/walk.hkx"),
+            PathBuf::from("a/idle.xml"),
+        ];
+        assert_eq!(
+            find_collision(&files, annotation_path).as_deref(),
+            Some("a/idle.hkx and a/idle.xml would both use a/idle.txt; process them separately")
+        );
+        assert_eq!(
+            find_collision(&files, |path| output_path(path, "same")),
+            None
+        );
+        assert!(find_collision(&files, |path| output_path(path, "amd64")).is_some());
+    }
+}
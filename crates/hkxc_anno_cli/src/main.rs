@@ -0,0 +1,32 @@
+mod args;
+mod cmd;
+mod output;
+mod status;
+
+use std::process::ExitCode;
+
+use clap::Parser as _;
+
+use crate::args::{Cli, Command};
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(cli.log_level)
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .init();
+
+    let format = cli.message_format;
+    let status = match cli.command {
+        Command::Dump(args) => cmd::dump(args, format).await,
+        Command::Apply(args) => cmd::apply(args, format).await,
+        Command::PreviewXml(args) => cmd::preview_xml(args, format).await,
+        Command::Validate(args) => cmd::validate(args, format).await,
+        Command::ExportTable(args) => cmd::export_table(args, format).await,
+        Command::ImportTable(args) => cmd::import_table(args, format).await,
+        Command::Retime(args) => cmd::retime(args, format).await,
+    };
+    status.into()
+}
@@ -0,0 +1,116 @@
+//! Printing of [`Record`]s in the `--message-format`.
+use serde_hkx_hkanno::{
+    editor::ApplyOutcome,
+    report::{to_json, to_json_line, Record},
+    Severity,
+};
+
+use crate::args::MessageFormat;
+
+/// Prints `records`: JSON to stdout, text to stdout with failures and skipped files on stderr.
+pub(crate) fn emit(records: &[Record<'_>], format: MessageFormat) {
+    match format {
+        MessageFormat::Text => records.iter().for_each(print_text),
+        MessageFormat::Json => match to_json(records) {
+            Ok(json) => println!("{json}"),
+            Err(err) => eprintln!("error: {err}"),
+        },
+        MessageFormat::JsonLines => {
+            for record in records {
+                match to_json_line(record) {
+                    Ok(line) => println!("{line}"),
+                    Err(err) => eprintln!("error: {err}"),
+                }
+            }
+        }
+    }
+}
+
+fn print_text(record: &Record<'_>) {
+    match record {
+        Record::Dump {
+            path,
+            annotation_path,
+            written,
+            ..
+        } => match written {
+            true => println!("{}: wrote {}", path.display(), annotation_path.display()),
+            false => println!(
+                "{}: kept existing {} (use --force to overwrite)",
+                path.display(),
+                annotation_path.display()
+            ),
+        },
+        Record::Apply {
+            path,
+            output,
+            outcome,
+            ..
+        } => match outcome {
+            ApplyOutcome::Written => println!("{}: wrote {}", path.display(), output.display()),
+            ApplyOutcome::Unchanged => println!("{}: unchanged", path.display()),
+        },
+        Record::Export { path, table, rows } => println!(
+            "{}: exported {rows} annotation(s) to {}",
+            path.display(),
+            table.display()
+        ),
+        Record::Retime {
+            path,
+            annotation_path,
+            changed,
+        } => match changed {
+            0 => println!("{}: unchanged", path.display()),
+            changed => println!(
+                "{}: retimed {changed} annotation(s) in {}",
+                path.display(),
+                annotation_path.display()
+            ),
+        },
+        Record::Diff { path, diff } => match diff.is_empty() {
+            true => println!("{}: no changes", path.display()),
+            false => println!("{}: would change\n{diff}", path.display()),
+        },
+        Record::Preview {
+            path, output, xml, ..
+        } => {
+            if let Some(output) = output {
+                println!("{}: wrote {}", path.display(), output.display());
+            }
+            if let Some(xml) = xml {
+                print!("{xml}");
+            }
+        }
+        Record::Validation {
+            annotation_path,
+            syntax_errors,
+            findings,
+            ..
+        } => {
+            let origin = annotation_path.display().to_string();
+            for err in syntax_errors {
+                println!("{}", err.render(&origin));
+            }
+            for finding in findings {
+                let severity = match finding.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                };
+                let section = match finding.ptr {
+                    0 => String::new(),
+                    ptr => format!(" #{ptr:04}"),
+                };
+                println!("{origin}:{section} {severity}: {}", finding.message);
+            }
+        }
+        Record::Skipped { path, reason } => eprintln!("{}: skipped, {reason}", path.display()),
+        Record::Error {
+            path: Some(path),
+            message,
+        } => eprintln!("{}: error: {message}", path.display()),
+        Record::Error {
+            path: None,
+            message,
+        } => eprintln!("error: {message}"),
+    }
+}
@@ -0,0 +1,24 @@
+use std::process::ExitCode;
+
+/// Exit status of `hkxc-anno-cli`.
+///
+/// Build scripts branch on these codes, so the value of an existing variant must never change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    /// Every file was processed.
+    Success = 0,
+    /// At least one input path or file failed; the others were still processed.
+    Failure = 1,
+    /// Invalid arguments. clap also exits with this code on parse errors.
+    Usage = 2,
+    /// `validate` found error-level issues (or warnings with `--deny-warnings`).
+    Invalid = 3,
+    /// The inputs contain no HKX file.
+    NoInput = 4,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        Self::from(status as u8)
+    }
+}
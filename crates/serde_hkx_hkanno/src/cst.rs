@@ -0,0 +1,401 @@
+//! Lossless hkanno syntax tree.
+//!
+//! [`Hkanno`]'s `Display` regenerates the whole text, dropping free comments,
+//! blank-line layout and the original spelling of numbers. [`HkannoCst`] instead
+//! keeps every line of the source verbatim and only rewrites the tokens that are
+//! edited, so hand-maintained hkanno files (e.g. sidecar files kept in version
+//! control) produce minimal diffs.
+//!
+//! ```txt
+//! # hkanno v2
+//! # my note            <- kept
+//! trackName: Root
+//! 0.5    Hit           <- `set_time(0, 0, 0.75)` only rewrites `0.5`
+//! ```
+use std::{borrow::Cow, fmt, ops::Range};
+
+use havok_types::NULL_STR;
+
+use crate::{
+    frame_at, parse_hkanno_str,
+    parser::{header_field, num_annotations_line, parse_line, split_sections, track_name_line},
+    Hkanno, HkannoDocument, HkannoError, HkannoParseError, DEFAULT_FPS,
+};
+
+/// What a [`CstLine`] is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CstLineKind {
+    /// Empty or whitespace-only line.
+    Blank,
+    /// Free `# ...` comment.
+    Comment,
+    /// Recognized header comment such as `# duration: 1.5`.
+    Header,
+    /// `trackName: ...` line of the `track`-th track.
+    TrackName { track: usize },
+    /// `# numAnnotations: ...` line of the `track`-th track.
+    NumAnnotations { track: usize },
+    /// The `index`-th annotation of the `track`-th track.
+    Annotation { track: usize, index: usize },
+}
+
+/// One source line, kept verbatim.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CstLine<'a> {
+    /// Line content without its line ending.
+    pub text: Cow<'a, str>,
+    /// Original line ending: `"\n"`, `"\r\n"`, or `""` for the last line.
+    pub ending: &'a str,
+    /// What the line is.
+    pub kind: CstLineKind,
+}
+
+/// Lossless syntax tree of one hkanno text.
+///
+/// Printing it with `Display` reproduces the source byte for byte until it is edited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HkannoCst<'a> {
+    lines: Vec<CstLine<'a>>,
+    /// Parsed view of `lines`, kept in sync by every edit.
+    hkanno: Hkanno<'static>,
+    /// Line index of each track name, and of each annotation per track.
+    tracks: Vec<(usize, Vec<usize>)>,
+}
+
+impl<'a> HkannoCst<'a> {
+    /// Parses hkanno text, keeping every line.
+    ///
+    /// # Errors
+    /// Same as [`parse_hkanno_str`].
+    pub fn parse(input: &'a str) -> Result<Self, HkannoParseError> {
+        let hkanno = parse_hkanno_str(input)?.into_static();
+
+        let mut lines = Vec::new();
+        let mut tracks: Vec<(usize, Vec<usize>)> = Vec::new();
+        for raw in input.split_inclusive('\n') {
+            let text = raw.trim_end_matches(['\r', '\n']);
+            let ending = &raw[text.len()..];
+
+            let kind = if text.trim().is_empty() {
+                CstLineKind::Blank
+            } else if text.trim_start().starts_with('#') {
+                match tracks.len().checked_sub(1) {
+                    None if parse_line(header_field, raw).is_ok() => CstLineKind::Header,
+                    Some(track) if parse_line(num_annotations_line, raw).is_ok() => {
+                        CstLineKind::NumAnnotations { track }
+                    }
+                    _ => CstLineKind::Comment,
+                }
+            } else if parse_line(track_name_line, raw).is_ok() {
+                tracks.push((lines.len(), Vec::new()));
+                CstLineKind::TrackName {
+                    track: tracks.len() - 1,
+                }
+            } else {
+                // `parse_hkanno_str` accepted the input, so any other line is an annotation.
+                let track = tracks.len() - 1;
+                let annotations = &mut tracks[track].1;
+                annotations.push(lines.len());
+                CstLineKind::Annotation {
+                    track,
+                    index: annotations.len() - 1,
+                }
+            };
+            lines.push(CstLine {
+                text: Cow::Borrowed(text),
+                ending,
+                kind,
+            });
+        }
+
+        Ok(Self {
+            lines,
+            hkanno,
+            tracks,
+        })
+    }
+
+    /// Returns every line in source order.
+    pub fn lines(&self) -> &[CstLine<'a>] {
+        &self.lines
+    }
+
+    /// Returns the parsed annotations, reflecting all edits made so far.
+    pub const fn hkanno(&self) -> &Hkanno<'static> {
+        &self.hkanno
+    }
+
+    /// Rewrites the time of one annotation, leaving the rest of its line untouched.
+    ///
+    /// The original notation is kept: a frame time (`f12` / `12f`) stays a frame
+    /// time if `time` is on a frame, otherwise seconds are written like `Display` does.
+    /// Setting the current value is a no-op, so the original spelling survives.
+    ///
+    /// # Errors
+    /// If the annotation does not exist.
+    pub fn set_time(&mut self, track: usize, index: usize, time: f32) -> Result<(), HkannoError> {
+        let line_index = self.annotation_line(track, index)?;
+        let ann = &mut self.hkanno.annotation_tracks[track].annotations[index];
+        if ann.time.to_bits() == time.to_bits() {
+            return Ok(());
+        }
+        ann.time = time;
+
+        let fps = self.hkanno.fps.unwrap_or(DEFAULT_FPS);
+        let line = &mut self.lines[line_index];
+        let (time_span, _) = annotation_spans(&line.text);
+        let old = &line.text[time_span.clone()];
+        let new = match frame_at(time, fps) {
+            Some(frame) if old.starts_with('f') => format!("f{frame}"),
+            Some(frame) if old.ends_with('f') => format!("{frame}f"),
+            _ => format!("{time:.6}"),
+        };
+        line.text.to_mut().replace_range(time_span, &new);
+        Ok(())
+    }
+
+    /// Rewrites the text of one annotation, leaving its time untouched.
+    ///
+    /// # Errors
+    /// If the annotation does not exist.
+    pub fn set_text(
+        &mut self,
+        track: usize,
+        index: usize,
+        text: Option<&str>,
+    ) -> Result<(), HkannoError> {
+        let line_index = self.annotation_line(track, index)?;
+        let ann = &mut self.hkanno.annotation_tracks[track].annotations[index];
+        if ann.text.as_deref() == text {
+            return Ok(());
+        }
+        ann.text = text.map(|text| Cow::Owned(text.to_string()));
+
+        let line = &mut self.lines[line_index];
+        let (_, text_span) = annotation_spans(&line.text);
+        line.text
+            .to_mut()
+            .replace_range(text_span, text.unwrap_or(NULL_STR));
+        Ok(())
+    }
+
+    /// Rewrites the name of one track.
+    ///
+    /// # Errors
+    /// If the track does not exist.
+    pub fn set_track_name(&mut self, track: usize, name: Option<&str>) -> Result<(), HkannoError> {
+        let (line_index, _) = *self
+            .tracks
+            .get(track)
+            .ok_or(HkannoError::AnnotationNotFound { track, index: None })?;
+        let current = &mut self.hkanno.annotation_tracks[track].track_name;
+        if current.as_deref() == name {
+            return Ok(());
+        }
+        *current = name.map(|name| Cow::Owned(name.to_string()));
+
+        let line = &mut self.lines[line_index];
+        let colon = line.text.find(':').map_or(line.text.len(), |i| i + 1);
+        let start = colon + (line.text.len() - colon - line.text[colon..].trim_start().len());
+        let end = line.text.len();
+        line.text
+            .to_mut()
+            .replace_range(start..end, name.unwrap_or(NULL_STR));
+        Ok(())
+    }
+
+    /// Applies the track names, times and texts of `hkanno` as minimal edits.
+    ///
+    /// Use this to save an edited [`Hkanno`] back into its original text.
+    ///
+    /// # Errors
+    /// [`HkannoError::CstLayoutChanged`] if `hkanno` has a different number of tracks
+    /// or annotations; in that case regenerate the text with `Display` instead.
+    pub fn update_from(&mut self, hkanno: &Hkanno<'_>) -> Result<(), HkannoError> {
+        let same_layout = hkanno.annotation_tracks.len() == self.tracks.len()
+            && hkanno
+                .annotation_tracks
+                .iter()
+                .zip(&self.tracks)
+                .all(|(track, (_, lines))| track.annotations.len() == lines.len());
+        if !same_layout {
+            return Err(HkannoError::CstLayoutChanged);
+        }
+
+        for (track_index, track) in hkanno.annotation_tracks.iter().enumerate() {
+            self.set_track_name(track_index, track.track_name.as_deref())?;
+            for (index, ann) in track.annotations.iter().enumerate() {
+                self.set_time(track_index, index, ann.time)?;
+                self.set_text(track_index, index, ann.text.as_deref())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the line index of an annotation.
+    fn annotation_line(&self, track: usize, index: usize) -> Result<usize, HkannoError> {
+        self.tracks
+            .get(track)
+            .and_then(|(_, lines)| lines.get(index).copied())
+            .ok_or(HkannoError::AnnotationNotFound {
+                track,
+                index: Some(index),
+            })
+    }
+}
+
+/// Applies every section of `document` to `input`, the document text it was
+/// parsed from, as minimal edits (see [`HkannoCst::update_from`]).
+///
+/// Text before the first `# ptr:` header, comments and the spelling of
+/// unchanged values are kept.
+///
+/// # Errors
+/// - If `input` is not a valid document.
+/// - [`HkannoError::CstLayoutChanged`] if `document` has a different number of
+///   sections, tracks or annotations.
+pub fn update_hkanno_document_str(
+    input: &str,
+    document: &HkannoDocument<'_>,
+) -> Result<String, HkannoError> {
+    let (sections, mut errors) = split_sections(input);
+    if !errors.is_empty() {
+        return Err(errors.swap_remove(0).into());
+    }
+    if sections.len() != document.animations.len() {
+        return Err(HkannoError::CstLayoutChanged);
+    }
+
+    let mut text = input[..sections[0].offset].to_string();
+    for (section, hkanno) in sections.iter().zip(&document.animations) {
+        let mut cst =
+            HkannoCst::parse(section.text).map_err(|e| e.rebase(input, section.offset))?;
+        cst.update_from(hkanno)?;
+        text.push_str(&cst.to_string());
+    }
+    Ok(text)
+}
+
+/// Byte ranges of the time token and of the text in an annotation line.
+fn annotation_spans(line: &str) -> (Range<usize>, Range<usize>) {
+    let is_space = |c: char| c == ' ' || c == '\t';
+
+    let time_start = line.len() - line.trim_start_matches(is_space).len();
+    let time_end = line[time_start..]
+        .find(is_space)
+        .map_or(line.len(), |i| time_start + i);
+    let text_start = line.len() - line[time_end..].trim_start_matches(is_space).len();
+    (time_start..time_end, text_start..line.len())
+}
+
+impl fmt::Display for HkannoCst<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            write!(f, "{}{}", line.text, line.ending)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "# hkanno v2\r\n\
+                         # keep this note\r\n\
+                         # duration: 1.50\r\n\
+                         \r\n\
+                         trackName: Root\r\n\
+                         # numAnnotations: 2\r\n\
+                         0.5    Hit   # not a comment\r\n\
+                         \t1.25 End\r\n\
+                         # trailing note";
+
+    #[test]
+    fn round_trip_is_lossless() {
+        let cst = HkannoCst::parse(INPUT).unwrap();
+        assert_eq!(cst.to_string(), INPUT);
+
+        let kinds: Vec<_> = cst.lines().iter().map(|line| line.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            [
+                CstLineKind::Header,
+                CstLineKind::Comment,
+                CstLineKind::Header,
+                CstLineKind::Blank,
+                CstLineKind::TrackName { track: 0 },
+                CstLineKind::NumAnnotations { track: 0 },
+                CstLineKind::Annotation { track: 0, index: 0 },
+                CstLineKind::Annotation { track: 0, index: 1 },
+                CstLineKind::Comment,
+            ]
+        );
+    }
+
+    #[test]
+    fn edits_touch_only_their_token() {
+        let mut cst = HkannoCst::parse(INPUT).unwrap();
+        cst.set_time(0, 0, 0.75).unwrap();
+        cst.set_text(0, 1, Some("Finish")).unwrap();
+        cst.set_time(0, 1, 1.25).unwrap(); // unchanged value keeps its spelling
+
+        let expected = INPUT
+            .replace("0.5    Hit", "0.750000    Hit")
+            .replace("\t1.25 End", "\t1.25 Finish");
+        assert_eq!(cst.to_string(), expected);
+        assert_eq!(
+            parse_hkanno_str(&expected).unwrap().annotation_tracks,
+            cst.hkanno().annotation_tracks
+        );
+
+        assert!(matches!(
+            cst.set_time(0, 2, 0.0),
+            Err(HkannoError::AnnotationNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn update_from_keeps_frame_notation() {
+        let input = "# fps: 30\ntrackName: Root\nf3 Hit\n";
+        let mut cst = HkannoCst::parse(input).unwrap();
+
+        let mut hkanno = cst.hkanno().clone();
+        hkanno.annotation_tracks[0].track_name = Some(Cow::Borrowed("Pelvis"));
+        hkanno.annotation_tracks[0].annotations[0].time = 0.2;
+        cst.update_from(&hkanno).unwrap();
+        assert_eq!(cst.to_string(), "# fps: 30\ntrackName: Pelvis\nf6 Hit\n");
+
+        hkanno.annotation_tracks[0].annotations.clear();
+        assert!(matches!(
+            cst.update_from(&hkanno),
+            Err(HkannoError::CstLayoutChanged)
+        ));
+    }
+
+    #[test]
+    fn documents_keep_every_section_verbatim() {
+        let input = "# shared note\n\n\
+                     # ptr: #0001\ntrackName: A\nf3 Hit   # keep\n\n\
+                     # ptr: #0002\n# fps: 60\ntrackName: B\n0.5 End\n";
+        let mut document = crate::parse_hkanno_document_str(input)
+            .unwrap()
+            .into_static();
+        document.animations[0].annotation_tracks[0].annotations[0].time = 0.2;
+        document.animations[1].annotation_tracks[0].annotations[0].time = 0.25;
+
+        let expected = input
+            .replace("f3 Hit", "f6 Hit")
+            .replace("0.5 End", "0.250000 End");
+        assert_eq!(
+            update_hkanno_document_str(input, &document).unwrap(),
+            expected
+        );
+
+        document.animations.pop();
+        assert!(matches!(
+            update_hkanno_document_str(input, &document),
+            Err(HkannoError::CstLayoutChanged)
+        ));
+    }
+}
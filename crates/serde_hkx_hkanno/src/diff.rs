@@ -0,0 +1,506 @@
+//! Structured diff between two sets of annotations.
+//!
+//! Used to review what an apply would change before anything is written.
+//!
+//! Tracks are matched by name first, then by position, so a renamed track is
+//! reported as a rename rather than a removal plus an addition. Within a track,
+//! annotations are aligned on their longest common subsequence; an unmatched
+//! pair that keeps its text is a retime, one that keeps its time is a retext.
+//!
+//! Times are compared with the same tolerance as validation, because hkanno text
+//! stores them with 6 decimals and re-parsing a dump must not count as an edit.
+use std::{collections::HashSet, fmt};
+
+use crate::{
+    validate::TIME_EPSILON, Annotation, AnnotationTrack, Hkanno, HkannoDocument, HkannoError,
+};
+
+/// Changes to every animation of a file.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HkannoDiff {
+    /// One entry per animation that changes, ordered by pointer.
+    pub animations: Vec<AnimationDiff>,
+}
+
+impl HkannoDiff {
+    /// Does applying change nothing?
+    pub fn is_empty(&self) -> bool {
+        self.animations.is_empty()
+    }
+}
+
+/// Changes to the annotation tracks of one animation.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnimationDiff {
+    /// Pointer of the animation class (`0` if unknown).
+    pub ptr: usize,
+    /// Only tracks that change.
+    pub tracks: Vec<TrackDiff>,
+}
+
+/// How a track changes.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum TrackChange {
+    /// Only in the new annotations.
+    Added,
+    /// Only in the old annotations.
+    Removed,
+    /// Same position, different name.
+    Renamed { old_name: Option<String> },
+    /// Same track, different annotations.
+    Modified,
+}
+
+/// Changes to one track.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackDiff {
+    /// How the track itself changes.
+    pub change: TrackChange,
+    /// Index in the old annotations.
+    pub old_index: Option<usize>,
+    /// Index in the new annotations.
+    pub new_index: Option<usize>,
+    /// Track name (the old name for [`TrackChange::Removed`]).
+    pub name: Option<String>,
+    /// Annotation changes, in track order.
+    pub annotations: Vec<AnnotationChange>,
+}
+
+/// A change to one annotation.
+///
+/// `index` is the index in the new track, except for [`Self::Deleted`] where it is the old one.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum AnnotationChange {
+    /// A new annotation.
+    Inserted {
+        index: usize,
+        time: f32,
+        text: Option<String>,
+    },
+    /// A removed annotation.
+    Deleted {
+        index: usize,
+        time: f32,
+        text: Option<String>,
+    },
+    /// Same text at another time.
+    Retimed {
+        index: usize,
+        old_time: f32,
+        new_time: f32,
+        text: Option<String>,
+    },
+    /// Same time with another text.
+    Retexted {
+        index: usize,
+        time: f32,
+        old_text: Option<String>,
+        new_text: Option<String>,
+    },
+}
+
+/// Computes what writing `new` over `old` would change.
+///
+/// A `new` section is compared with the `old` section of the same pointer; a
+/// single pointer-less section is compared with the only old section, mirroring
+/// [`HkannoDocument::write_to_classmap`]. Old sections without a new section are
+/// left untouched by a write and therefore not reported.
+///
+/// # Errors
+/// The errors [`HkannoDocument::write_to_classmap`] would return if `old` holds
+/// the file's animations: a pointer without an old section, a pointer-less
+/// section when `old` has none or several sections, or a duplicate pointer.
+pub fn diff_documents(
+    old: &HkannoDocument<'_>,
+    new: &HkannoDocument<'_>,
+) -> Result<HkannoDiff, HkannoError> {
+    let mut seen = HashSet::new();
+    let mut animations = Vec::new();
+    for new in &new.animations {
+        if !seen.insert(new.ptr) {
+            return Err(HkannoError::DuplicateHkannoSection { ptr: new.ptr });
+        }
+        let old = match (new.ptr, old.animations.as_slice()) {
+            (0, [only]) => only,
+            (0, []) => return Err(HkannoError::MissingHkaAnimationClass),
+            (0, animations) => {
+                return Err(HkannoError::MultipleHkaAnimationFound {
+                    count: animations.len(),
+                })
+            }
+            (ptr, animations) => animations
+                .iter()
+                .find(|old| old.ptr == ptr)
+                .ok_or(HkannoError::HkaAnimationNotFoundAt { ptr })?,
+        };
+        let diff = diff_hkanno(old, new);
+        if !diff.tracks.is_empty() {
+            animations.push(diff);
+        }
+    }
+    animations.sort_by_key(|diff| diff.ptr);
+    Ok(HkannoDiff { animations })
+}
+
+/// Computes the track changes from `old` to `new`.
+///
+/// The returned `ptr` is `old.ptr`, or `new.ptr` when the old one is unknown.
+pub fn diff_hkanno(old: &Hkanno<'_>, new: &Hkanno<'_>) -> AnimationDiff {
+    let old_tracks = &old.annotation_tracks;
+    let new_tracks = &new.annotation_tracks;
+
+    // Pair tracks by name, then leftovers by position.
+    let mut pairs: Vec<(Option<usize>, Option<usize>)> = Vec::new();
+    let mut old_used = vec![false; old_tracks.len()];
+    let mut new_paired = vec![None; new_tracks.len()];
+    for (new_index, new_track) in new_tracks.iter().enumerate() {
+        let same_name = old_tracks
+            .iter()
+            .enumerate()
+            .find(|(i, old_track)| !old_used[*i] && old_track.track_name == new_track.track_name);
+        if let Some((old_index, _)) = same_name {
+            old_used[old_index] = true;
+            new_paired[new_index] = Some(old_index);
+        }
+    }
+    let mut old_left = (0..old_tracks.len()).filter(|&i| !old_used[i]);
+    for (new_index, paired) in new_paired.iter_mut().enumerate() {
+        if paired.is_none() {
+            *paired = old_left.next();
+        }
+        pairs.push((*paired, Some(new_index)));
+    }
+    pairs.extend(old_left.map(|old_index| (Some(old_index), None)));
+
+    let tracks = pairs
+        .into_iter()
+        .filter_map(|(old_index, new_index)| {
+            let old_track = old_index.map(|i| &old_tracks[i]);
+            let new_track = new_index.map(|i| &new_tracks[i]);
+            diff_track(old_track, new_track).map(|(change, annotations)| TrackDiff {
+                change,
+                old_index,
+                new_index,
+                name: new_track.or(old_track).and_then(track_name),
+                annotations,
+            })
+        })
+        .collect();
+
+    AnimationDiff {
+        ptr: if old.ptr == 0 { new.ptr } else { old.ptr },
+        tracks,
+    }
+}
+
+fn track_name(track: &AnnotationTrack<'_>) -> Option<String> {
+    track.track_name.as_deref().map(str::to_string)
+}
+
+/// Returns `None` if the tracks are identical.
+fn diff_track(
+    old: Option<&AnnotationTrack<'_>>,
+    new: Option<&AnnotationTrack<'_>>,
+) -> Option<(TrackChange, Vec<AnnotationChange>)> {
+    let old_annotations = old.map_or(&[][..], |track| &track.annotations);
+    let new_annotations = new.map_or(&[][..], |track| &track.annotations);
+    let annotations = diff_annotations(old_annotations, new_annotations);
+
+    let change = match (old, new) {
+        (None, _) => TrackChange::Added,
+        (_, None) => TrackChange::Removed,
+        (Some(old), Some(new)) if old.track_name != new.track_name => TrackChange::Renamed {
+            old_name: track_name(old),
+        },
+        _ if annotations.is_empty() => return None,
+        _ => TrackChange::Modified,
+    };
+    Some((change, annotations))
+}
+
+fn same_time(a: f32, b: f32) -> bool {
+    a.to_bits() == b.to_bits() || (a - b).abs() <= TIME_EPSILON
+}
+
+fn text(ann: &Annotation<'_>) -> Option<String> {
+    ann.text.as_deref().map(str::to_string)
+}
+
+/// Aligns annotations on their longest common subsequence of equal `(time, text)`.
+fn diff_annotations(old: &[Annotation<'_>], new: &[Annotation<'_>]) -> Vec<AnnotationChange> {
+    let same =
+        |a: &Annotation<'_>, b: &Annotation<'_>| same_time(a.time, b.time) && a.text == b.text;
+
+    // lcs[i][j]: LCS length of old[i..] and new[j..]
+    let mut lcs = vec![vec![0_usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if same(&old[i], &new[j]) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut deleted, mut inserted) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && same(&old[i], &new[j]) {
+            flush(old, new, &mut deleted, &mut inserted, &mut changes);
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            inserted.push(j);
+            j += 1;
+        } else {
+            deleted.push(i);
+            i += 1;
+        }
+    }
+    flush(old, new, &mut deleted, &mut inserted, &mut changes);
+    changes
+}
+
+/// Turns one run of unmatched annotations into changes, pairing deletions with
+/// insertions that keep the text (retime) or the time (retext).
+fn flush(
+    old: &[Annotation<'_>],
+    new: &[Annotation<'_>],
+    deleted: &mut Vec<usize>,
+    inserted: &mut Vec<usize>,
+    changes: &mut Vec<AnnotationChange>,
+) {
+    let mut pending: Vec<Option<usize>> = deleted.drain(..).map(Some).collect();
+    for j in inserted.drain(..) {
+        let ann = &new[j];
+        let paired = pending.iter_mut().find_map(|slot| {
+            let i = (*slot)?;
+            let change = if old[i].text == ann.text {
+                AnnotationChange::Retimed {
+                    index: j,
+                    old_time: old[i].time,
+                    new_time: ann.time,
+                    text: text(ann),
+                }
+            } else if same_time(old[i].time, ann.time) {
+                AnnotationChange::Retexted {
+                    index: j,
+                    time: ann.time,
+                    old_text: text(&old[i]),
+                    new_text: text(ann),
+                }
+            } else {
+                return None;
+            };
+            *slot = None;
+            Some(change)
+        });
+        changes.push(paired.unwrap_or_else(|| AnnotationChange::Inserted {
+            index: j,
+            time: ann.time,
+            text: text(ann),
+        }));
+    }
+    changes.extend(
+        pending
+            .into_iter()
+            .flatten()
+            .map(|i| AnnotationChange::Deleted {
+                index: i,
+                time: old[i].time,
+                text: text(&old[i]),
+            }),
+    );
+}
+
+/// Writes `-`/`+` lines in hkanno syntax, one `@@ #ptr @@` hunk per animation.
+impl fmt::Display for HkannoDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = |time: f32, text: &Option<String>| {
+            format!(
+                "{time:.6} {}",
+                text.as_deref().unwrap_or(havok_types::NULL_STR)
+            )
+        };
+        let name = |name: &Option<String>| {
+            format!(
+                "trackName: {}",
+                name.as_deref().unwrap_or(havok_types::NULL_STR)
+            )
+        };
+
+        for animation in &self.animations {
+            writeln!(f, "@@ #{:04} @@", animation.ptr)?;
+            for track in &animation.tracks {
+                match &track.change {
+                    TrackChange::Added => writeln!(f, "+{}", name(&track.name))?,
+                    TrackChange::Removed => writeln!(f, "-{}", name(&track.name))?,
+                    TrackChange::Renamed { old_name } => {
+                        writeln!(f, "-{}", name(old_name))?;
+                        writeln!(f, "+{}", name(&track.name))?;
+                    }
+                    TrackChange::Modified => writeln!(f, " {}", name(&track.name))?,
+                }
+
+                for change in &track.annotations {
+                    match change {
+                        AnnotationChange::Inserted { time, text, .. } => {
+                            writeln!(f, "+{}", line(*time, text))?;
+                        }
+                        AnnotationChange::Deleted { time, text, .. } => {
+                            writeln!(f, "-{}", line(*time, text))?;
+                        }
+                        AnnotationChange::Retimed {
+                            old_time,
+                            new_time,
+                            text,
+                            ..
+                        } => {
+                            writeln!(f, "-{}", line(*old_time, text))?;
+                            writeln!(f, "+{}", line(*new_time, text))?;
+                        }
+                        AnnotationChange::Retexted {
+                            time,
+                            old_text,
+                            new_text,
+                            ..
+                        } => {
+                            writeln!(f, "-{}", line(*time, old_text))?;
+                            writeln!(f, "+{}", line(*time, new_text))?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_hkanno_str;
+
+    fn diff(old: &str, new: &str) -> AnimationDiff {
+        diff_hkanno(
+            &parse_hkanno_str(old).unwrap(),
+            &parse_hkanno_str(new).unwrap(),
+        )
+    }
+
+    #[test]
+    fn identical_annotations_have_no_changes() {
+        let text = "trackName: A\n0.3333333 x\n";
+        let dumped = parse_hkanno_str(text).unwrap().to_string(); // rounded to 6 decimals
+        assert_eq!(diff(text, &dumped).tracks, []);
+    }
+
+    #[test]
+    fn classifies_annotation_changes() {
+        let old = "trackName: A\n0.1 keep\n0.2 move\n0.3 old\n0.4 gone\n";
+        let new = "trackName: A\n0.1 keep\n0.25 move\n0.3 new\n0.5 added\n";
+        let tracks = diff(old, new).tracks;
+
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].change, TrackChange::Modified);
+        assert_eq!(
+            tracks[0].annotations,
+            [
+                AnnotationChange::Retimed {
+                    index: 1,
+                    old_time: 0.2,
+                    new_time: 0.25,
+                    text: Some("move".to_string()),
+                },
+                AnnotationChange::Retexted {
+                    index: 2,
+                    time: 0.3,
+                    old_text: Some("old".to_string()),
+                    new_text: Some("new".to_string()),
+                },
+                AnnotationChange::Inserted {
+                    index: 3,
+                    time: 0.5,
+                    text: Some("added".to_string()),
+                },
+                AnnotationChange::Deleted {
+                    index: 3,
+                    time: 0.4,
+                    text: Some("gone".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn classifies_track_changes() {
+        let old = "trackName: A\n0.1 a\ntrackName: B\n0.1 b\ntrackName: C\n";
+        let new = "trackName: B\n0.1 b\ntrackName: Z\ntrackName: A\n0.1 a\ntrackName: D\n";
+        let changes: Vec<_> = diff(old, new)
+            .tracks
+            .into_iter()
+            .map(|track| (track.change, track.name))
+            .collect();
+
+        assert_eq!(
+            changes,
+            [
+                (
+                    TrackChange::Renamed {
+                        old_name: Some("C".to_string())
+                    },
+                    Some("Z".to_string())
+                ),
+                (TrackChange::Added, Some("D".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn unified_rendering() {
+        let old = parse_hkanno_str("# ptr: #0003\ntrackName: A\n0.1 a\n").unwrap();
+        let new = parse_hkanno_str("# ptr: #0003\ntrackName: A\n0.2 a\n").unwrap();
+        let diff = diff_documents(&old.into(), &new.into()).unwrap();
+
+        assert_eq!(
+            diff.to_string(),
+            "@@ #0003 @@\n trackName: A\n-0.100000 a\n+0.200000 a\n"
+        );
+        let none = HkannoDocument { animations: vec![] };
+        assert!(diff_documents(&none, &none).unwrap().is_empty());
+    }
+
+    #[test]
+    fn documents_fail_like_apply_would() {
+        let section = |text: &str| parse_hkanno_str(text).unwrap().into_static();
+        let file = |ptrs: &[usize]| HkannoDocument {
+            animations: ptrs
+                .iter()
+                .map(|ptr| section(&format!("# ptr: #{ptr}\ntrackName: A\n")))
+                .collect(),
+        };
+        let edit = |text: &str| HkannoDocument::from(section(text));
+
+        let missing = diff_documents(&file(&[3]), &edit("# ptr: #0009\ntrackName: A\n0.1 a\n"));
+        assert!(matches!(
+            missing,
+            Err(HkannoError::HkaAnimationNotFoundAt { ptr: 9 })
+        ));
+
+        let ambiguous = diff_documents(&file(&[3, 7]), &edit("trackName: A\n0.1 a\n"));
+        assert!(matches!(
+            ambiguous,
+            Err(HkannoError::MultipleHkaAnimationFound { count: 2 })
+        ));
+
+        let only = diff_documents(&file(&[3]), &edit("trackName: A\n0.1 a\n")).unwrap();
+        assert_eq!(only.animations.len(), 1);
+    }
+}
@@ -0,0 +1,350 @@
+//! Multi-animation hkanno documents.
+//!
+//! Paired animations and some packed files hold more than one `hkaAnimation`-derived
+//! class. An [`HkannoDocument`] keeps one [`Hkanno`] section per animation, keyed by
+//! its class pointer:
+//!
+//! ```txt
+//! # ptr: #0003
+//! # hkanno v2
+//! # numOriginalFrames: 38
+//! ...
+//!
+//! # ptr: #0007
+//! # hkanno v2
+//! ...
+//! ```
+//!
+//! Each section is a plain hkanno text whose `# ptr:` header starts the section,
+//! so a single-animation document is identical to an [`Hkanno`] text.
+use havok_classes::Classes;
+use rayon::prelude::*;
+use serde_hkx_features::ClassMap;
+use snafu::ResultExt as _;
+use std::{collections::HashSet, fmt, path::Path};
+
+use crate::verify::verify_output;
+use crate::{
+    hkanno_from_class, is_hka_animation_derived, serialize_class_map, Hkanno, HkannoError,
+    MissingHkaAnimationClassSnafu, OutFormat, SerdeHkxFeatureSnafu, TimeNotation, ValidationMode,
+    ValidationOptions,
+};
+
+/// The annotations of every `hkaAnimation`-derived class in one HKX file.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct HkannoDocument<'a> {
+    /// One section per animation, ordered by [`Hkanno::ptr`].
+    pub animations: Vec<Hkanno<'a>>,
+}
+
+impl<'a> HkannoDocument<'a> {
+    /// Converts a borrowed document into an owned `'static` document.
+    pub fn into_static(self) -> HkannoDocument<'static> {
+        HkannoDocument {
+            animations: self
+                .animations
+                .into_iter()
+                .map(Hkanno::into_static)
+                .collect(),
+        }
+    }
+
+    /// Write every section back into the animation at its pointer.
+    ///
+    /// Each section is written like [`Hkanno::write_to_classmap`].
+    /// Animations without a section are left untouched.
+    ///
+    /// # Errors
+    /// - If a pointer does not refer to an `hkaAnimation`-derived class.
+    /// - If two sections share a pointer.
+    pub fn write_to_classmap(self, class_map: &mut ClassMap<'a>) -> Result<(), HkannoError> {
+        let mut seen = HashSet::new();
+        for hkanno in self.animations {
+            let ptr = hkanno.ptr;
+            if !seen.insert(ptr) {
+                return Err(HkannoError::DuplicateHkannoSection { ptr });
+            }
+            hkanno.write_to_classmap(class_map)?;
+        }
+        Ok(())
+    }
+
+    /// Updates the given HKX/XML file bytes with every section of `self`.
+    ///
+    /// This is the multi-animation counterpart of [`Hkanno::update_hkx_bytes`].
+    ///
+    /// # Errors
+    /// Same as [`Hkanno::update_hkx_bytes`], plus the pointer errors of [`Self::write_to_classmap`].
+    pub fn update_hkx_bytes(
+        self,
+        bytes: &mut Vec<u8>,
+        format: OutFormat,
+        input: &Path,
+        validation: &ValidationOptions,
+    ) -> Result<Vec<u8>, HkannoError> {
+        let mut text = String::new();
+
+        // Deserialize bytes → ClassMap
+        let mut class_map: ClassMap<'_> =
+            serde_hkx_features::serde::de::deserialize(bytes, &mut text, input)
+                .context(SerdeHkxFeatureSnafu)?;
+        self.update_class_map(&mut class_map, format, input, validation, None)
+    }
+
+    /// Validates every section against the animation it targets in `class_map`,
+    /// logging every issue.
+    ///
+    /// # Errors
+    /// If a section's animation is not found, or in [`ValidationMode::Strict`] if any
+    /// error-level issue is found.
+    pub(crate) fn check(
+        &self,
+        class_map: &ClassMap<'_>,
+        validation: &ValidationOptions,
+        input: &Path,
+    ) -> Result<(), HkannoError> {
+        if validation.mode != ValidationMode::Off {
+            for hkanno in &self.animations {
+                let timing = hkanno.target_timing(class_map, validation.fps)?;
+                hkanno.check(timing, validation, input)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates, writes every section into `class_map` and serializes it.
+    ///
+    /// With `verify` set to the input file's class map, the output is deserialized again
+    /// and must hold the requested annotations and otherwise the same classes.
+    ///
+    /// # Errors
+    /// Same as [`Self::update_hkx_bytes`], plus the verification errors.
+    pub(crate) fn update_class_map(
+        self,
+        class_map: &mut ClassMap<'a>,
+        format: OutFormat,
+        input: &Path,
+        validation: &ValidationOptions,
+        verify: Option<&ClassMap<'_>>,
+    ) -> Result<Vec<u8>, HkannoError> {
+        self.check(class_map, validation, input)?;
+
+        let requested = verify.map(|original| (original, self.clone()));
+        self.write_to_classmap(class_map)?; // Update annotations (pure memory operation)
+        let output = serialize_class_map(class_map, format, input)?;
+
+        if let Some((original, requested)) = requested {
+            verify_output(original, &output, &requested, input)?;
+        }
+        Ok(output)
+    }
+}
+
+impl<'a> From<Hkanno<'a>> for HkannoDocument<'a> {
+    fn from(hkanno: Hkanno<'a>) -> Self {
+        Self {
+            animations: vec![hkanno],
+        }
+    }
+}
+
+impl HkannoDocument<'_> {
+    /// Formats every section as hkanno text, writing times in `notation`.
+    ///
+    /// See [`Hkanno::display`].
+    pub fn display(&self, notation: TimeNotation) -> impl fmt::Display + '_ {
+        DocumentDisplay {
+            document: self,
+            notation,
+        }
+    }
+}
+
+/// [`fmt::Display`] adapter returned by [`HkannoDocument::display`].
+struct DocumentDisplay<'d, 'a> {
+    document: &'d HkannoDocument<'a>,
+    notation: TimeNotation,
+}
+
+impl fmt::Display for HkannoDocument<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display(TimeNotation::Seconds).fmt(f)
+    }
+}
+
+impl fmt::Display for DocumentDisplay<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Each section starts with its own `# ptr:` header.
+        self.document
+            .animations
+            .iter()
+            .try_for_each(|hkanno| write!(f, "{}", hkanno.display(self.notation)))
+    }
+}
+
+/// Parses every `hkaAnimation`-derived class of a `ClassMap` into an [`HkannoDocument`].
+///
+/// Unlike [`parse_hkanno_borrowed`](crate::parse_hkanno_borrowed), files with several
+/// animations are accepted; sections are ordered by pointer.
+///
+/// # Errors
+/// - [`HkannoError::MissingHkaAnimationClass`] – no `hkaAnimation`-derived class found.
+pub fn parse_hkanno_document_borrowed(
+    class_map: ClassMap<'_>,
+    fps: f32,
+) -> Result<HkannoDocument<'_>, HkannoError> {
+    let mut animation_classes: Vec<_> = class_map
+        .into_par_iter()
+        .filter(|(_, class)| is_hka_animation_derived(class))
+        .collect();
+    if animation_classes.is_empty() {
+        return MissingHkaAnimationClassSnafu.fail();
+    }
+    animation_classes.par_sort_unstable_by_key(|(ptr, _)| *ptr);
+
+    let animations = animation_classes
+        .into_iter()
+        .map(|(ptr, class): (usize, Classes<'_>)| hkanno_from_class(ptr, class, fps))
+        .collect::<Result<_, _>>()?;
+    Ok(HkannoDocument { animations })
+}
+
+/// Parses a HKX or XML file into an [`HkannoDocument`] directly from raw bytes.
+///
+/// This is the multi-animation counterpart of [`parse_as_hkanno`](crate::parse_as_hkanno).
+///
+/// # Errors
+/// If deserialization fails or the file has no `hkaAnimation`-derived class.
+#[inline]
+pub fn parse_as_hkanno_document<'a>(
+    bytes: &'a Vec<u8>,
+    text: &'a mut String,
+    path: &Path,
+    fps: f32,
+) -> Result<HkannoDocument<'a>, HkannoError> {
+    let class_map: ClassMap<'a> = serde_hkx_features::serde::de::deserialize(bytes, text, path)
+        .context(SerdeHkxFeatureSnafu)?;
+    parse_hkanno_document_borrowed(class_map, fps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_hkanno_document_str, Annotation, AnnotationTrack, DEFAULT_FPS};
+    use havok_classes::{hkaAnimation, hkaSplineCompressedAnimation};
+    use std::borrow::Cow;
+
+    fn spline(duration: f32) -> Classes<'static> {
+        let class = hkaSplineCompressedAnimation {
+            parent: hkaAnimation {
+                m_duration: duration,
+                ..Default::default()
+            },
+            m_numFrames: 30,
+            ..Default::default()
+        };
+        Classes::hkaSplineCompressedAnimation(class.into())
+    }
+
+    fn hkanno(ptr: usize, text: &str) -> Hkanno<'_> {
+        Hkanno {
+            ptr,
+            num_original_frames: 0,
+            duration: 0.0,
+            num_annotation_tracks: None,
+            fps: None,
+            annotation_tracks: vec![AnnotationTrack {
+                track_name: Some(Cow::Borrowed("T")),
+                num_annotations: None,
+                annotations: vec![Annotation {
+                    time: 0.5,
+                    text: Some(Cow::Borrowed(text)),
+                }],
+            }],
+        }
+    }
+
+    fn track_texts(class_map: &ClassMap<'_>, ptr: usize) -> Vec<String> {
+        let Some(Classes::hkaSplineCompressedAnimation(class)) = class_map.get(&ptr) else {
+            panic!("expected spline at #{ptr:04}");
+        };
+        class.parent.m_annotationTracks[0]
+            .m_annotations
+            .iter()
+            .filter_map(|ann| ann.m_text.get_ref().as_deref().map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn multi_animation_round_trip() {
+        let class_map: ClassMap<'_> = [(3, spline(1.0)), (7, spline(2.0))].into_iter().collect();
+        let doc = parse_hkanno_document_borrowed(class_map, DEFAULT_FPS).unwrap();
+        assert_eq!(doc.animations.len(), 2);
+
+        let text = doc.to_string();
+        assert!(text.starts_with("# ptr: #0003\n"));
+        assert!(text.contains("# ptr: #0007\n"));
+
+        let reparsed = parse_hkanno_document_str(&text).unwrap();
+        let ptrs: Vec<_> = reparsed.animations.iter().map(|h| h.ptr).collect();
+        assert_eq!(ptrs, [3, 7]);
+    }
+
+    #[test]
+    fn writes_each_section_by_ptr() {
+        let mut class_map: ClassMap<'_> =
+            [(3, spline(1.0)), (7, spline(2.0))].into_iter().collect();
+        let doc = HkannoDocument {
+            animations: vec![hkanno(7, "Seven"), hkanno(3, "Three")],
+        };
+        doc.write_to_classmap(&mut class_map).unwrap();
+
+        assert_eq!(track_texts(&class_map, 3), ["Three"]);
+        assert_eq!(track_texts(&class_map, 7), ["Seven"]);
+    }
+
+    #[test]
+    fn rejects_unknown_or_duplicate_ptr() {
+        let mut class_map: ClassMap<'_> =
+            [(3, spline(1.0)), (7, spline(2.0))].into_iter().collect();
+
+        let doc = HkannoDocument {
+            animations: vec![hkanno(3, "a"), hkanno(5, "b")],
+        };
+        assert!(matches!(
+            doc.write_to_classmap(&mut class_map),
+            Err(HkannoError::HkaAnimationNotFoundAt { ptr: 5 })
+        ));
+
+        let doc = HkannoDocument {
+            animations: vec![hkanno(3, "a"), hkanno(3, "b")],
+        };
+        assert!(matches!(
+            doc.write_to_classmap(&mut class_map),
+            Err(HkannoError::DuplicateHkannoSection { ptr: 3 })
+        ));
+    }
+
+    #[test]
+    fn ptr_survives_single_animation_round_trip() {
+        let mut class_map: ClassMap<'_> =
+            [(1, spline(1.0)), (5, spline(1.0))].into_iter().collect();
+        class_map.swap_remove(&1);
+
+        let text = parse_hkanno_document_borrowed(class_map.clone(), DEFAULT_FPS)
+            .unwrap()
+            .to_string();
+        assert!(text.starts_with("# ptr: #0005\n"));
+
+        let doc = parse_hkanno_document_str(&text).unwrap();
+        assert_eq!(doc.animations[0].ptr, 5);
+        doc.write_to_classmap(&mut class_map).unwrap();
+
+        // A stale pointer is rejected instead of writing to another class.
+        let stale = HkannoDocument::from(hkanno(1, "x"));
+        assert!(matches!(
+            stale.write_to_classmap(&mut class_map),
+            Err(HkannoError::HkaAnimationNotFoundAt { ptr: 1 })
+        ));
+    }
+}
@@ -1,50 +1,280 @@
 use snafu::ResultExt;
-use std::{path::Path, str::FromStr as _};
-use tokio::fs;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr as _,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tokio::{fs, io::AsyncWriteExt as _};
 
-use crate::{parse_as_hkanno, parse_hkanno_str, HkannoError, IoSnafu, OutFormat};
+use crate::{
+    diff_documents, parse_hkanno_document_str, update_hkanno_document_str, AnnotationFilter,
+    HkannoDiff, HkannoDocument, HkannoError, HkxFormat, IoSnafu, LoadedHkx, OutFormat, Retime,
+    ValidationOptions,
+};
+
+/// Options for [`apply_hkanno`].
+#[derive(Debug, Clone, Default)]
+pub struct ApplyOptions {
+    /// How the annotations are checked against the animation before writing.
+    ///
+    /// [`ValidationMode::Strict`](crate::ValidationMode::Strict) refuses to write when
+    /// any error-level issue (e.g. a malformed `PIE.@` line) is found.
+    pub validation: ValidationOptions,
+
+    /// What to keep of an existing output file before it is replaced.
+    pub backup: BackupPolicy,
+
+    /// Deserialize the produced bytes again before writing and refuse to write unless
+    /// they hold the requested annotations and otherwise unchanged classes.
+    ///
+    /// Classes are compared exactly, so use it when saving in the input's own format.
+    pub verify: bool,
+}
+
+/// Output format name for [`apply_hkanno`] that keeps the input's format.
+pub const SAME_AS_INPUT: &str = "same";
+
+/// What [`apply_hkanno`] did with one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyReport {
+    /// Whether the output was written.
+    pub outcome: ApplyOutcome,
+    /// Format detected from the input's header (`None` if not recognized).
+    pub input_format: Option<HkxFormat>,
+}
+
+/// Whether [`apply_hkanno`] wrote the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ApplyOutcome {
+    /// The output file was written.
+    Written,
+    /// The output is the input and its annotations already match, so it was left untouched.
+    Unchanged,
+}
+
+/// How [`apply_hkanno`] backs up an existing output file before replacing it.
+///
+/// Backups are copies, so the output stays intact until the new file is complete.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum BackupPolicy {
+    /// Keep no backup.
+    #[default]
+    None,
+    /// Copy to `<output>.bak` (e.g. `idle.hkx.bak`), replacing the previous backup.
+    Sibling,
+    /// Copy to `<output>.<timestamp>.bak` next to the output, keeping every backup.
+    Timestamped,
+    /// Copy to `<dir>/<file name>.<path hash>.<timestamp>.bak`, keeping only the
+    /// newest `keep` backups of that output (`0` keeps all).
+    ///
+    /// The hash of the output's absolute path keeps outputs with the same name in
+    /// different directories apart. Pruning only removes files named like that.
+    Directory { dir: PathBuf, keep: usize },
+}
 
 /// Read hkanno from `xml`, `hkx` file.
 ///
+/// Files with several `hkaAnimation` objects produce one `# ptr: #NNNN` section per animation.
+/// `fps` is used to derive `# numOriginalFrames` of non-spline animations (see [`DEFAULT_FPS`](crate::DEFAULT_FPS)).
+///
 /// # Return
 /// - Returns hkanno string.
 ///
 /// # Errors
 /// - Returns `HkannoError` if reading the input file fails, or if parsing the hkx bytes fails.
-pub async fn read_hkanno(input: &Path) -> Result<String, HkannoError> {
-    let bytes = fs::read(&input)
-        .await
-        .with_context(|_| IoSnafu { path: input })?;
-
-    let mut buffer = String::new();
-    parse_as_hkanno(&bytes, &mut buffer, input).map(|anno| anno.to_string())
+pub async fn read_hkanno(input: &Path, fps: f32) -> Result<String, HkannoError> {
+    let loaded = LoadedHkx::open(input).await?;
+    loaded.extract(fps).map(|doc| doc.to_string())
 }
 
 /// Apply hkanno to `xml`, `hkx` file.
 ///
+/// `format` is `amd64`, `win32`, `xml`, or [`SAME_AS_INPUT`] to write the format
+/// detected from the input's header, so e.g. an LE animation is not converted to SE.
+///
+/// When updating in place (`output` is the input file) in the input's own format and
+/// the annotations equal the existing ones (times compared with the 6 decimals of
+/// hkanno text), nothing is written and [`ApplyOutcome::Unchanged`] is returned,
+/// so file bytes and timestamps only change on real edits. The annotations are
+/// still validated, so strict validation rejects an invalid file either way.
+///
 /// # Errors
-/// - Returns `HkannoError` if reading the input file fails,
-///   if parsing the hkanno string fails, or if updating the hkx bytes fails.
+/// - Returns `HkannoError` if reading the input file fails, if `format` is [`SAME_AS_INPUT`]
+///   and the input format is unknown, if parsing the hkanno string fails,
+///   if strict validation fails, if updating the hkx bytes fails, or if `verify` is on
+///   and the produced bytes diverge (nothing is written then).
 pub async fn apply_hkanno(
     input: &Path,
     output: &Path,
     hkanno: &str,
     format: &str,
-) -> Result<(), HkannoError> {
-    let mut bytes = fs::read(&input)
-        .await
-        .with_context(|_| IoSnafu { path: input })?;
+    options: &ApplyOptions,
+) -> Result<ApplyReport, HkannoError> {
+    let loaded = LoadedHkx::open(input).await?;
+    apply_hkanno_loaded(&loaded, output, hkanno, format, options).await
+}
 
-    let format = OutFormat::from_str(format).map_err(|_| HkannoError::InvalidOutputFormat {
-        format: format.to_string(),
-    })?;
-    let updated = parse_hkanno_str(hkanno)?.update_hkx_bytes(&mut bytes, format, input)?;
+/// [`apply_hkanno`] on an already loaded file.
+///
+/// # Errors
+/// Same as [`apply_hkanno`], except for reading the input.
+pub async fn apply_hkanno_loaded(
+    loaded: &LoadedHkx,
+    output: &Path,
+    hkanno: &str,
+    format: &str,
+    options: &ApplyOptions,
+) -> Result<ApplyReport, HkannoError> {
+    let document = parse_hkanno_document_str(hkanno)?;
+    apply_document_loaded(loaded, output, document, format, options).await
+}
 
-    fs::write(&output, updated)
-        .await
-        .with_context(|_| IoSnafu { path: output })?;
+/// [`apply_hkanno_loaded`] with annotations that are already parsed.
+///
+/// # Errors
+/// Same as [`apply_hkanno_loaded`], except for parsing the hkanno string.
+pub async fn apply_document_loaded<'a>(
+    loaded: &'a LoadedHkx,
+    output: &Path,
+    document: HkannoDocument<'a>,
+    format: &str,
+    options: &ApplyOptions,
+) -> Result<ApplyReport, HkannoError> {
+    let input_format = loaded.format();
+    let format = resolve_format(format, input_format)?;
 
-    Ok(())
+    let same_file = input_format.map(HkxFormat::out_format) == Some(format)
+        && is_same_file(output, loaded.path()).await;
+    if same_file && diff_documents(&loaded.extract(options.validation.fps)?, &document)?.is_empty()
+    {
+        document.check(loaded.class_map(), &options.validation, loaded.path())?;
+        return Ok(ApplyReport {
+            outcome: ApplyOutcome::Unchanged,
+            input_format,
+        });
+    }
+
+    let updated = loaded.apply(document, format, &options.validation, options.verify)?;
+    write_atomic(output, &updated, &options.backup).await?;
+    Ok(ApplyReport {
+        outcome: ApplyOutcome::Written,
+        input_format,
+    })
+}
+
+/// Format of a structured annotation document for [`apply_structured`].
+///
+/// The document has the shape of a serialized [`HkannoDocument`]. Only the
+/// tracks are required; `ptr`, `num_original_frames` and `duration` default to
+/// "not declared", and a missing `text` or `track_name` is `null`:
+///
+/// ```yaml
+/// animations:
+///   - ptr: 3                     # optional; omit for a file with one animation
+///     annotation_tracks:
+///       - track_name: PairedRoot
+///         annotations:
+///           - { time: 0.1, text: MCO_DodgeOpen }
+///           - { time: 0.4, text: MCO_DodgeClose }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuredFormat {
+    /// `.json`
+    Json,
+    /// `.yaml`, `.yml`
+    Yaml,
+}
+
+impl StructuredFormat {
+    /// Picks the format from a `.json`, `.yaml` or `.yml` extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Parses an annotation document in this format.
+    ///
+    /// # Errors
+    /// If `text` is not a valid document.
+    pub fn parse(self, text: &str) -> Result<HkannoDocument<'static>, HkannoError> {
+        let result = match self {
+            Self::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            Self::Yaml => serde_yaml_ng::from_str(text).map_err(|e| e.to_string()),
+        };
+        result.map_err(|message| HkannoError::InvalidStructuredDocument {
+            format: self,
+            message,
+        })
+    }
+}
+
+impl fmt::Display for StructuredFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "JSON",
+            Self::Yaml => "YAML",
+        })
+    }
+}
+
+/// [`apply_hkanno`] with annotations given as a JSON or YAML document instead of hkanno text.
+///
+/// # Errors
+/// Same as [`apply_hkanno`], with [`HkannoError::InvalidStructuredDocument`]
+/// instead of hkanno syntax errors.
+pub async fn apply_structured(
+    input: &Path,
+    output: &Path,
+    document: &str,
+    structured: StructuredFormat,
+    format: &str,
+    options: &ApplyOptions,
+) -> Result<ApplyReport, HkannoError> {
+    let loaded = LoadedHkx::open(input).await?;
+    apply_structured_loaded(&loaded, output, document, structured, format, options).await
+}
+
+/// [`apply_structured`] on an already loaded file.
+///
+/// # Errors
+/// Same as [`apply_structured`], except for reading the input.
+pub async fn apply_structured_loaded(
+    loaded: &LoadedHkx,
+    output: &Path,
+    document: &str,
+    structured: StructuredFormat,
+    format: &str,
+    options: &ApplyOptions,
+) -> Result<ApplyReport, HkannoError> {
+    let document = structured.parse(document)?;
+    apply_document_loaded(loaded, output, document, format, options).await
+}
+
+/// Whether `a` and `b` name the same existing file, e.g. `./idle.hkx` and `idle.hkx`.
+async fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a).await, fs::canonicalize(b).await) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Parses an output format name, resolving [`SAME_AS_INPUT`] to the detected input format.
+fn resolve_format(format: &str, input_format: Option<HkxFormat>) -> Result<OutFormat, HkannoError> {
+    if format.eq_ignore_ascii_case(SAME_AS_INPUT) {
+        return input_format
+            .map(HkxFormat::out_format)
+            .ok_or(HkannoError::UnknownInputFormat);
+    }
+    OutFormat::from_str(format).map_err(|_| HkannoError::InvalidOutputFormat {
+        format: format.to_string(),
+    })
 }
 
 /// Apply hkanno to `xml`, `hkx` file and return updated xml string.
@@ -55,10 +285,478 @@ pub async fn apply_hkanno(
 /// - Returns `HkannoError` if reading the input file fails,
 ///   if parsing the hkanno string fails, or if updating the hkx bytes fails.
 pub async fn hkanno_apply_xml_string(input: &Path, hkanno: &str) -> Result<String, HkannoError> {
-    let mut bytes = fs::read(&input)
-        .await
-        .with_context(|_| IoSnafu { path: input })?;
+    let loaded = LoadedHkx::open(input).await?;
+    hkanno_apply_xml_string_loaded(&loaded, hkanno)
+}
 
-    let new_xml = parse_hkanno_str(hkanno)?.update_hkx_bytes(&mut bytes, OutFormat::Xml, input)?;
+/// [`hkanno_apply_xml_string`] on an already loaded file.
+///
+/// # Errors
+/// Same as [`hkanno_apply_xml_string`], except for reading the input.
+pub fn hkanno_apply_xml_string_loaded(
+    loaded: &LoadedHkx,
+    hkanno: &str,
+) -> Result<String, HkannoError> {
+    let document = parse_hkanno_document_str(hkanno)?;
+    let new_xml = loaded.apply(document, OutFormat::Xml, &ValidationOptions::off(), false)?;
     Ok(String::from_utf8(new_xml)?)
 }
+
+/// Dry-run of [`apply_hkanno`]: reports what applying hkanno to `input` would change.
+///
+/// Nothing is written. Render the result with `Display` for a unified-diff-like text.
+///
+/// # Errors
+/// - Returns `HkannoError` if reading the input file fails,
+///   if parsing the hkx bytes fails, if parsing the hkanno string fails, or if a
+///   section does not target exactly one animation (the errors apply would fail with).
+pub async fn diff_hkanno_changes(
+    input: &Path,
+    hkanno: &str,
+    fps: f32,
+) -> Result<HkannoDiff, HkannoError> {
+    let loaded = LoadedHkx::open(input).await?;
+    diff_hkanno_changes_loaded(&loaded, hkanno, fps)
+}
+
+/// [`diff_hkanno_changes`] on an already loaded file.
+///
+/// # Errors
+/// Same as [`diff_hkanno_changes`], except for reading the input.
+pub fn diff_hkanno_changes_loaded(
+    loaded: &LoadedHkx,
+    hkanno: &str,
+    fps: f32,
+) -> Result<HkannoDiff, HkannoError> {
+    let current = loaded.extract(fps)?;
+    let edited = parse_hkanno_document_str(hkanno)?;
+    diff_documents(&current, &edited)
+}
+
+/// Annotations changed by [`retime_hkanno`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retimed {
+    /// The retimed annotations.
+    pub document: HkannoDocument<'static>,
+    /// The hkanno text with the new times; comments, blank lines and the
+    /// notation of each time (seconds, `f12` or `12f`) are kept.
+    pub text: String,
+    /// How many annotation times changed.
+    pub changed: usize,
+}
+
+/// Changes the annotation times of hkanno text for `input` (see [`Retime`]).
+///
+/// Each section first takes the duration and frame count of its animation in `input`,
+/// so [`Retime::Remap`] maps onto the file's actual duration and [`Retime::Shift`]
+/// clamps to it. `fps` is used for the frame count of non-spline animations.
+///
+/// # Errors
+/// - Returns `HkannoError` if reading the input file fails, if parsing the hkanno
+///   string fails, if a section's animation is not found, or if `retime` is invalid.
+pub async fn retime_hkanno(
+    input: &Path,
+    hkanno: &str,
+    retime: Retime,
+    filter: &AnnotationFilter,
+    fps: f32,
+) -> Result<Retimed, HkannoError> {
+    let loaded = LoadedHkx::open(input).await?;
+    retime_hkanno_loaded(&loaded, hkanno, retime, filter, fps)
+}
+
+/// [`retime_hkanno`] on an already loaded file.
+///
+/// # Errors
+/// Same as [`retime_hkanno`], except for reading the input.
+pub fn retime_hkanno_loaded(
+    loaded: &LoadedHkx,
+    hkanno: &str,
+    retime: Retime,
+    filter: &AnnotationFilter,
+    fps: f32,
+) -> Result<Retimed, HkannoError> {
+    let mut document = parse_hkanno_document_str(hkanno)?.into_static();
+    for section in &mut document.animations {
+        let timing = section.target_timing(loaded.class_map(), fps)?;
+        section.duration = timing.duration;
+        section.num_original_frames = timing.num_frames;
+    }
+    let changed = document.retime(retime, filter)?;
+    let text = update_hkanno_document_str(hkanno, &document)?;
+    Ok(Retimed {
+        document,
+        text,
+        changed,
+    })
+}
+
+/// Writes `bytes` to `output` without ever leaving a partially written file.
+///
+/// The data is written and flushed to a temporary file in the same directory,
+/// the existing output is backed up per `backup`, and the temporary file is then
+/// renamed over `output`. On failure the temporary file is removed and `output`
+/// is left untouched.
+///
+/// # Errors
+/// If writing, backing up or renaming fails.
+async fn write_atomic(
+    output: &Path,
+    bytes: &[u8],
+    backup: &BackupPolicy,
+) -> Result<(), HkannoError> {
+    /// Distinguishes temporary files of concurrent writes within this process.
+    static TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let file_name = output.file_name().unwrap_or_default().to_string_lossy();
+    let temp = output.with_file_name(format!(
+        ".{file_name}.{}-{}.tmp",
+        std::process::id(),
+        TEMP_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = async {
+        let mut file = fs::File::create(&temp)
+            .await
+            .with_context(|_| IoSnafu { path: &temp })?;
+        file.write_all(bytes)
+            .await
+            .with_context(|_| IoSnafu { path: &temp })?;
+        file.sync_all()
+            .await
+            .with_context(|_| IoSnafu { path: &temp })?;
+        drop(file);
+
+        if fs::try_exists(output).await.unwrap_or(false) {
+            back_up(output, backup).await?;
+        }
+        fs::rename(&temp, output)
+            .await
+            .with_context(|_| IoSnafu { path: output })
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp).await;
+    }
+    result
+}
+
+/// `chrono` format of backup timestamps.
+///
+/// Backups are stamped in UTC, which never goes backwards at daylight saving changes,
+/// so the stamps sort lexicographically, oldest first.
+const BACKUP_TIMESTAMP: &str = "%F_%H-%M-%S-%3f";
+
+/// Copies the existing `output` according to `policy`.
+///
+/// # Errors
+/// If copying, or pruning old backups, fails.
+async fn back_up(output: &Path, policy: &BackupPolicy) -> Result<(), HkannoError> {
+    let file_name = output.file_name().unwrap_or_default().to_string_lossy();
+
+    let (backup, reserved) = match policy {
+        BackupPolicy::None => return Ok(()),
+        BackupPolicy::Sibling => (output.with_file_name(format!("{file_name}.bak")), false),
+        BackupPolicy::Timestamped => {
+            let dir = output.parent().unwrap_or(Path::new(""));
+            (reserve_backup(dir, &format!("{file_name}.")).await?, true)
+        }
+        BackupPolicy::Directory { dir, .. } => {
+            fs::create_dir_all(dir)
+                .await
+                .with_context(|_| IoSnafu { path: dir })?;
+            (
+                reserve_backup(dir, &directory_backup_prefix(output)).await?,
+                true,
+            )
+        }
+    };
+
+    if let Err(source) = fs::copy(output, &backup).await {
+        // An empty reserved file would pass for a backup and count toward `keep`.
+        if reserved {
+            let _ = fs::remove_file(&backup).await;
+        }
+        return Err(HkannoError::IoError {
+            source,
+            path: backup,
+        });
+    }
+
+    if let BackupPolicy::Directory { dir, keep } = policy {
+        if *keep > 0 {
+            prune_backups(dir, &directory_backup_prefix(output), *keep).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Name prefix of the backups of `output` in a [`BackupPolicy::Directory`]:
+/// `<file name>.<path hash>.`
+///
+/// The hash of the absolute path keeps same-named files of different folders
+/// (common among Skyrim animations) apart, both in names and in pruning.
+fn directory_backup_prefix(output: &Path) -> String {
+    let file_name = output.file_name().unwrap_or_default().to_string_lossy();
+    let path = std::path::absolute(output).unwrap_or_else(|_| output.to_path_buf());
+    let hash = crate::hash::file_hash(path.as_os_str().as_encoded_bytes());
+    format!("{file_name}.{hash}.")
+}
+
+/// Creates an empty `<dir>/<prefix><timestamp>.bak` that no other backup uses yet.
+///
+/// If a backup of the same millisecond exists, the next millisecond is tried, so
+/// an earlier backup is never overwritten.
+///
+/// # Errors
+/// If the file cannot be created.
+async fn reserve_backup(dir: &Path, prefix: &str) -> Result<PathBuf, HkannoError> {
+    loop {
+        let stamp = chrono::Utc::now().format(BACKUP_TIMESTAMP);
+        let backup = dir.join(format!("{prefix}{stamp}.bak"));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)
+            .await
+        {
+            Ok(_) => return Ok(backup),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+            Err(source) => {
+                return Err(HkannoError::IoError {
+                    source,
+                    path: backup,
+                })
+            }
+        }
+    }
+}
+
+/// Removes all but the newest `keep` backups named `<prefix><timestamp>.bak` in `dir`.
+///
+/// Only names whose middle is exactly a [`BACKUP_TIMESTAMP`] are considered, so
+/// other files in `dir` are never removed.
+///
+/// # Errors
+/// If `dir` cannot be read or a backup cannot be removed.
+async fn prune_backups(dir: &Path, prefix: &str, keep: usize) -> Result<(), HkannoError> {
+    let mut backups = Vec::new();
+
+    let mut entries = fs::read_dir(dir)
+        .await
+        .with_context(|_| IoSnafu { path: dir })?;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .with_context(|_| IoSnafu { path: dir })?
+    {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_backup = name
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(".bak"))
+            .is_some_and(is_backup_timestamp);
+        if is_backup {
+            backups.push(name);
+        }
+    }
+
+    // Timestamps sort lexicographically, oldest first.
+    backups.sort_unstable();
+    let excess = backups.len().saturating_sub(keep);
+    for name in &backups[..excess] {
+        let path = dir.join(name);
+        fs::remove_file(&path)
+            .await
+            .with_context(|_| IoSnafu { path: &path })?;
+    }
+    Ok(())
+}
+
+/// Whether `stamp` is a timestamp exactly as [`BACKUP_TIMESTAMP`] writes it.
+fn is_backup_timestamp(stamp: &str) -> bool {
+    chrono::NaiveDateTime::parse_from_str(stamp, BACKUP_TIMESTAMP)
+        .is_ok_and(|time| time.format(BACKUP_TIMESTAMP).to_string() == stamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_structured_documents() {
+        let json = r#"{"animations":[{"ptr":3,"annotation_tracks":[
+            {"track_name":"PairedRoot","annotations":[{"time":0.1,"text":"MCO_DodgeOpen"}]}
+        ]}]}"#;
+        let yaml = "
+animations:
+  - ptr: 3
+    annotation_tracks:
+      - track_name: PairedRoot
+        annotations:
+          - { time: 0.1, text: MCO_DodgeOpen }
+";
+        let from_json = StructuredFormat::Json.parse(json).unwrap();
+        assert_eq!(from_json, StructuredFormat::Yaml.parse(yaml).unwrap());
+
+        let hkanno = &from_json.animations[0];
+        assert_eq!((hkanno.ptr, hkanno.duration), (3, 0.0));
+        let track = &hkanno.annotation_tracks[0];
+        assert_eq!(track.track_name.as_deref(), Some("PairedRoot"));
+        assert_eq!(track.annotations[0].text.as_deref(), Some("MCO_DodgeOpen"));
+
+        let err = StructuredFormat::Yaml
+            .parse("animations: [{annotation_tracks: 1}]")
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            HkannoError::InvalidStructuredDocument {
+                format: StructuredFormat::Yaml,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn structured_format_from_extension() {
+        let format = |path: &str| StructuredFormat::from_path(Path::new(path));
+        assert_eq!(format("idle.JSON"), Some(StructuredFormat::Json));
+        assert_eq!(format("idle.yml"), Some(StructuredFormat::Yaml));
+        assert_eq!(format("idle.txt"), None);
+    }
+
+    /// An empty scratch directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hkanno_editor_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// File names in `dir`, sorted.
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn same_file_compares_canonical_paths() {
+        let dir = test_dir("same_file");
+        let input = dir.join("idle.hkx");
+        std::fs::write(&input, b"").unwrap();
+
+        assert!(is_same_file(&dir.join(".").join("idle.hkx"), &input).await);
+        assert!(!is_same_file(&dir.join("other.hkx"), &input).await);
+    }
+
+    #[tokio::test]
+    async fn write_atomic_replaces_output_and_cleans_up() {
+        let dir = test_dir("atomic");
+        let output = dir.join("idle.hkx");
+        write_atomic(&output, b"new", &BackupPolicy::None)
+            .await
+            .unwrap();
+        write_atomic(&output, b"newer", &BackupPolicy::None)
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&output).unwrap(), b"newer");
+        assert_eq!(names(&dir), ["idle.hkx"]);
+    }
+
+    #[tokio::test]
+    async fn sibling_and_timestamped_backups_keep_the_old_bytes() {
+        let dir = test_dir("sibling");
+        let output = dir.join("idle.hkx");
+        std::fs::write(&output, b"v1").unwrap();
+
+        write_atomic(&output, b"v2", &BackupPolicy::Sibling)
+            .await
+            .unwrap();
+        write_atomic(&output, b"v3", &BackupPolicy::Sibling)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(dir.join("idle.hkx.bak")).unwrap(), b"v2");
+
+        write_atomic(&output, b"v4", &BackupPolicy::Timestamped)
+            .await
+            .unwrap();
+        write_atomic(&output, b"v5", &BackupPolicy::Timestamped)
+            .await
+            .unwrap();
+        let stamped: Vec<_> = names(&dir)
+            .into_iter()
+            .filter(|name| name != "idle.hkx.bak")
+            .filter_map(|name| {
+                Some(
+                    name.strip_prefix("idle.hkx.")?
+                        .strip_suffix(".bak")?
+                        .to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(stamped.len(), 2, "{stamped:?}");
+        assert!(stamped.iter().all(|stamp| is_backup_timestamp(stamp)));
+        let oldest = dir.join(format!("idle.hkx.{}.bak", stamped[0]));
+        assert_eq!(std::fs::read(oldest).unwrap(), b"v3");
+        assert_eq!(std::fs::read(&output).unwrap(), b"v5");
+    }
+
+    #[tokio::test]
+    async fn directory_backups_are_kept_apart_and_pruned_per_output() {
+        let dir = test_dir("directory");
+        let backups = dir.join("backups");
+        let policy = BackupPolicy::Directory {
+            dir: backups.clone(),
+            keep: 2,
+        };
+        let (a, b) = (dir.join("a/idle.hkx"), dir.join("b/idle.hkx"));
+        for output in [&a, &b] {
+            std::fs::create_dir_all(output.parent().unwrap()).unwrap();
+            std::fs::write(output, b"v0").unwrap();
+        }
+        std::fs::create_dir_all(&backups).unwrap();
+        let prefix = directory_backup_prefix(&a);
+        let foreign = [
+            "idle.hkx.user-copy.bak".to_string(),
+            format!("{prefix}2020-01-01_00-00-00-000-mine.bak"),
+        ];
+        for name in &foreign {
+            std::fs::write(backups.join(name), b"keep me").unwrap();
+        }
+
+        // Same millisecond or not, every save of `a` gets its own backup before pruning.
+        for version in 1..=3u8 {
+            write_atomic(&a, &[version], &policy).await.unwrap();
+        }
+        write_atomic(&b, b"b1", &policy).await.unwrap();
+
+        let all = names(&backups);
+        let of_a: Vec<_> = all
+            .iter()
+            .filter(|n| n.starts_with(&prefix) && !foreign.contains(n))
+            .collect();
+        assert_eq!(of_a.len(), 2, "{all:?}");
+        assert_eq!(std::fs::read(backups.join(of_a[1])).unwrap(), [2]);
+        let of_b: Vec<_> = all
+            .iter()
+            .filter(|n| n.starts_with(&directory_backup_prefix(&b)))
+            .collect();
+        assert_eq!(of_b.len(), 1, "{all:?}");
+        assert!(foreign.iter().all(|name| all.contains(name)));
+    }
+
+    #[test]
+    fn backup_timestamps_must_match_exactly() {
+        assert!(is_backup_timestamp("2024-05-01_13-02-03-004"));
+        assert!(!is_backup_timestamp("2024-05-01_13-02-03"));
+        assert!(!is_backup_timestamp("2024-05-01_13-02-03-004-1"));
+        assert!(!is_backup_timestamp("user-copy"));
+    }
+}
@@ -0,0 +1,360 @@
+//! Typed view of annotation texts.
+//!
+//! Skyrim mods give some annotation texts a meaning beyond an event name:
+//!
+//! ```txt
+//! 0.100000 animmotion 0 120.5 0                  <- root motion (x y z), keyword in any case
+//! 0.200000 AnimRotation 90                       <- root rotation (degrees)
+//! 0.300000 SpecialFrames_Invincible{"Duration":0.5}
+//! 0.400000 PIE.@CASTSPELL|0x01|MyMod.esp         <- payload instruction
+//! ```
+//!
+//! [`AnnotationEvent::parse`] turns such a text into a typed value, and its
+//! `Display` writes it back byte for byte.
+use std::{borrow::Cow, fmt};
+
+use winnow::{
+    ascii::{float, space1, Caseless},
+    combinator::{alt, eof, preceded, repeat, terminated},
+    token::{one_of, take_till, take_while},
+    ModalResult, Parser as _,
+};
+
+/// A number that remembers how it was written (e.g. `1.50`, `+2`, `1e3`).
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Number<'a> {
+    /// Parsed value.
+    pub value: f32,
+    /// Original spelling, written back by `Display`.
+    pub raw: Cow<'a, str>,
+}
+
+impl From<f32> for Number<'_> {
+    fn from(value: f32) -> Self {
+        Self {
+            value,
+            raw: Cow::Owned(value.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Number<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+/// The character starting a payload instruction name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PayloadPrefix {
+    /// `@`: native instruction.
+    Native,
+    /// `$`: custom instruction.
+    Custom,
+    /// `!`: async instruction.
+    Async,
+}
+
+impl PayloadPrefix {
+    /// Returns the prefix character.
+    pub const fn as_char(self) -> char {
+        match self {
+            Self::Native => '@',
+            Self::Custom => '$',
+            Self::Async => '!',
+        }
+    }
+}
+
+/// A payload instruction such as `PIE.@CASTSPELL|0x01|MyMod.esp`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PayloadInstruction<'a> {
+    /// Event host before the `.`, typically `PIE`.
+    pub event: Cow<'a, str>,
+    /// Instruction prefix.
+    pub prefix: PayloadPrefix,
+    /// Instruction name, e.g. `CASTSPELL`.
+    pub name: Cow<'a, str>,
+    /// `|`-separated parameters, kept as written.
+    pub params: Vec<Cow<'a, str>>,
+}
+
+/// Typed annotation text.
+///
+/// Texts that match none of the known shapes, or that use spacing a typed
+/// variant would not reproduce exactly, are kept as [`Self::Unknown`], so
+/// `AnnotationEvent::parse(text).to_string() == text` always holds.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "camelCase")]
+pub enum AnnotationEvent<'a> {
+    /// `animmotion <x> <y> <z>`: root translation.
+    AnimMotion {
+        /// The keyword as written, e.g. `animmotion` or `AnimMotion`.
+        #[serde(default = "anim_motion_keyword")]
+        keyword: Cow<'a, str>,
+        x: Number<'a>,
+        y: Number<'a>,
+        z: Number<'a>,
+    },
+    /// `animrotation <degrees>`: root rotation.
+    AnimRotation {
+        /// The keyword as written, e.g. `animrotation` or `AnimRotation`.
+        #[serde(default = "anim_rotation_keyword")]
+        keyword: Cow<'a, str>,
+        degrees: Number<'a>,
+    },
+    /// `SpecialFrames_Invincible{...}`: invincibility frames with JSON options.
+    Invincible {
+        /// The JSON object, as written.
+        json: Cow<'a, str>,
+    },
+    /// `<event>.<prefix><name>|<param>|...`: payload instruction.
+    Payload(PayloadInstruction<'a>),
+    /// Any other text.
+    Unknown(Cow<'a, str>),
+}
+
+impl<'a> AnnotationEvent<'a> {
+    /// Parses an annotation text, borrowing from `text`.
+    pub fn parse(text: &'a str) -> Self {
+        let typed = alt((anim_motion, anim_rotation, invincible, payload)).parse(text);
+        match typed {
+            // Only accept a typed value that writes back to the exact same text.
+            Ok(event) if event.to_string() == text => event,
+            _ => Self::Unknown(Cow::Borrowed(text)),
+        }
+    }
+
+    /// Parses the JSON options of [`Self::Invincible`].
+    ///
+    /// Returns `None` for other variants.
+    pub fn json_value(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::Invincible { json } => serde_json::from_str(json).ok(),
+            _ => None,
+        }
+    }
+
+    /// Converts a borrowed event into an owned `'static` event.
+    pub fn into_static(self) -> AnnotationEvent<'static> {
+        let owned = |s: Cow<'_, str>| Cow::Owned(s.into_owned());
+        let number = |n: Number<'_>| Number {
+            value: n.value,
+            raw: owned(n.raw),
+        };
+
+        match self {
+            Self::AnimMotion { keyword, x, y, z } => AnnotationEvent::AnimMotion {
+                keyword: owned(keyword),
+                x: number(x),
+                y: number(y),
+                z: number(z),
+            },
+            Self::AnimRotation { keyword, degrees } => AnnotationEvent::AnimRotation {
+                keyword: owned(keyword),
+                degrees: number(degrees),
+            },
+            Self::Invincible { json } => AnnotationEvent::Invincible { json: owned(json) },
+            Self::Payload(payload) => AnnotationEvent::Payload(PayloadInstruction {
+                event: owned(payload.event),
+                prefix: payload.prefix,
+                name: owned(payload.name),
+                params: payload.params.into_iter().map(owned).collect(),
+            }),
+            Self::Unknown(text) => AnnotationEvent::Unknown(owned(text)),
+        }
+    }
+}
+
+impl fmt::Display for AnnotationEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AnimMotion { keyword, x, y, z } => write!(f, "{keyword} {x} {y} {z}"),
+            Self::AnimRotation { keyword, degrees } => write!(f, "{keyword} {degrees}"),
+            Self::Invincible { json } => write!(f, "SpecialFrames_Invincible{json}"),
+            Self::Payload(payload) => {
+                write!(
+                    f,
+                    "{}.{}{}",
+                    payload.event,
+                    payload.prefix.as_char(),
+                    payload.name
+                )?;
+                payload
+                    .params
+                    .iter()
+                    .try_for_each(|param| write!(f, "|{param}"))
+            }
+            Self::Unknown(text) => f.write_str(text),
+        }
+    }
+}
+
+impl crate::Annotation<'_> {
+    /// Returns the typed view of this annotation's text.
+    ///
+    /// A missing text is [`AnnotationEvent::Unknown`] with the null string.
+    pub fn event(&self) -> AnnotationEvent<'_> {
+        AnnotationEvent::parse(self.text.as_deref().unwrap_or(havok_types::NULL_STR))
+    }
+}
+
+fn number<'a>(input: &mut &'a str) -> ModalResult<Number<'a>> {
+    float
+        .with_taken()
+        .map(|(value, raw): (f32, &str)| Number {
+            value,
+            raw: Cow::Borrowed(raw),
+        })
+        .parse_next(input)
+}
+
+fn anim_motion_keyword() -> Cow<'static, str> {
+    Cow::Borrowed("animmotion")
+}
+
+fn anim_rotation_keyword() -> Cow<'static, str> {
+    Cow::Borrowed("animrotation")
+}
+
+fn anim_motion<'a>(input: &mut &'a str) -> ModalResult<AnnotationEvent<'a>> {
+    winnow::seq! {
+        AnnotationEvent::AnimMotion {
+            keyword: Caseless("animmotion").take().map(Cow::Borrowed),
+            _: space1,
+            x: number,
+            _: space1,
+            y: number,
+            _: space1,
+            z: terminated(number, eof),
+        }
+    }
+    .parse_next(input)
+}
+
+fn anim_rotation<'a>(input: &mut &'a str) -> ModalResult<AnnotationEvent<'a>> {
+    winnow::seq! {
+        AnnotationEvent::AnimRotation {
+            keyword: Caseless("animrotation").take().map(Cow::Borrowed),
+            _: space1,
+            degrees: terminated(number, eof),
+        }
+    }
+    .parse_next(input)
+}
+
+fn invincible<'a>(input: &mut &'a str) -> ModalResult<AnnotationEvent<'a>> {
+    preceded(
+        "SpecialFrames_Invincible",
+        take_while(0.., |_| true).verify(|json: &str| {
+            serde_json::from_str::<serde_json::Value>(json).is_ok_and(|value| value.is_object())
+        }),
+    )
+    .map(|json: &str| AnnotationEvent::Invincible {
+        json: Cow::Borrowed(json),
+    })
+    .parse_next(input)
+}
+
+fn payload<'a>(input: &mut &'a str) -> ModalResult<AnnotationEvent<'a>> {
+    winnow::seq! {
+        PayloadInstruction {
+            event: take_till(1.., ['.', ' ', '\t']).map(Cow::Borrowed),
+            _: ".",
+            prefix: one_of(['@', '$', '!']).map(|c| match c {
+                '@' => PayloadPrefix::Native,
+                '$' => PayloadPrefix::Custom,
+                _ => PayloadPrefix::Async,
+            }),
+            name: take_till(1.., '|').map(Cow::Borrowed),
+            params: repeat(0.., preceded("|", take_till(0.., '|').map(Cow::Borrowed))),
+            _: eof,
+        }
+    }
+    .map(AnnotationEvent::Payload)
+    .parse_next(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_events_round_trip() {
+        let texts = [
+            "animmotion 0 120.50 -1e2",
+            "animrotation 90",
+            r#"SpecialFrames_Invincible{"Duration":0.5}"#,
+            "PIE.@CASTSPELL|0x01|MyMod.esp|",
+            "PIE.$Custom",
+        ];
+        for text in texts {
+            let event = AnnotationEvent::parse(text);
+            assert!(
+                !matches!(event, AnnotationEvent::Unknown(_)),
+                "{text} should be typed"
+            );
+            assert_eq!(event.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn keywords_match_in_any_case() {
+        for text in ["AnimMotion 1.0 2.0 3.0", "ANIMROTATION 45"] {
+            let event = AnnotationEvent::parse(text);
+            assert!(
+                matches!(
+                    event,
+                    AnnotationEvent::AnimMotion { .. } | AnnotationEvent::AnimRotation { .. }
+                ),
+                "{text} should be typed"
+            );
+            assert_eq!(event.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn parses_payload_fields() {
+        let AnnotationEvent::Payload(payload) = AnnotationEvent::parse("PIE.!SGVF|Speed|1.5")
+        else {
+            panic!("expected payload");
+        };
+        assert_eq!(payload.event, "PIE");
+        assert_eq!(payload.prefix, PayloadPrefix::Async);
+        assert_eq!(payload.name, "SGVF");
+        assert_eq!(payload.params, ["Speed", "1.5"]);
+    }
+
+    #[test]
+    fn motion_keeps_number_spelling() {
+        let AnnotationEvent::AnimMotion { y, .. } = AnnotationEvent::parse("animmotion 0 1.50 0")
+        else {
+            panic!("expected animmotion");
+        };
+        assert_eq!(y.value, 1.5);
+        assert_eq!(y.raw, "1.50");
+    }
+
+    #[test]
+    fn other_texts_are_unknown() {
+        for text in [
+            "MCO_DodgeOpen",
+            "animmotion 1 2",
+            "animmotion  1 2 3",
+            "SpecialFrames_Invincible{broken",
+            "Weapon.Swing",
+            "",
+        ] {
+            let event = AnnotationEvent::parse(text);
+            assert_eq!(event, AnnotationEvent::Unknown(Cow::Borrowed(text)));
+            assert_eq!(event.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn invincible_json_value() {
+        let event = AnnotationEvent::parse(r#"SpecialFrames_Invincible{"Duration":0.5}"#);
+        assert_eq!(event.json_value().unwrap()["Duration"], 0.5);
+    }
+}
@@ -1,55 +1,421 @@
+//! Collects `.hkx`/`.xml` files from files and directories.
+//!
+//! Directories are walked recursively. [`CollectOptions`] limits the walk:
+//!
+//! - `max_depth`: how deep to descend below each input directory.
+//! - `include`/`exclude`: glob patterns matched against the path relative to the
+//!   input directory, e.g. `**/_1stperson/**`.
+//! - `animations_only`: skip files without an `hkaAnimation`-derived class
+//!   (skeletons, behaviors, unrelated XML), checked without deserializing.
+//!   Files whose header cannot be classified are kept.
+//! - `.hkannoignore`: a file in any walked directory listing patterns to skip,
+//!   in a subset of `.gitignore` syntax:
+//!
+//! ```txt
+//! # comment
+//! _1stperson/        <- directory at any depth
+//! /behaviors         <- anchored to the directory of this file
+//! *skeleton*.hkx
+//! !important.hkx     <- re-include
+//! ```
+use crate::read_has_animation_class;
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
 use rayon::prelude::*;
 use snafu::ResultExt as _;
 use std::{
-    fs,
+    fs, io,
     path::{Path, PathBuf},
 };
 
-/// Collect all `.hkx`, `.xml` files from the given input paths (files or directories).
-pub fn par_collect_hkx_files(input_paths: Vec<PathBuf>) -> Result<Vec<PathBuf>, CollectError> {
-    let (files, errors): (Vec<_>, Vec<_>) = input_paths
-        .into_par_iter()
-        .map(collect_from_path)
-        .partition_map(|result| match result {
-            Ok(paths) => rayon::iter::Either::Left(paths),
-            Err(err) => rayon::iter::Either::Right(err),
-        });
+/// Name of the per-directory ignore file.
+pub const IGNORE_FILE_NAME: &str = ".hkannoignore";
+
+/// Options for [`par_collect_hkx_files_with`].
+#[derive(Debug, Clone)]
+pub struct CollectOptions {
+    /// How many directory levels to descend below an input directory.
+    ///
+    /// `Some(0)` only collects the files directly inside it; `None` is unlimited.
+    pub max_depth: Option<usize>,
+
+    /// Glob patterns a file must match to be collected (all files if empty).
+    pub include: Vec<String>,
+
+    /// Glob patterns of files and directories to skip.
+    pub exclude: Vec<String>,
+
+    /// Honor [`IGNORE_FILE_NAME`] files in walked directories.
+    pub ignore_files: bool,
+
+    /// Descend into symlinked directories and collect symlinked files.
+    ///
+    /// Symlinks leading back into a directory being walked are skipped.
+    pub follow_symlinks: bool,
 
-    if !errors.is_empty() {
-        let err = CollectError::CollectErrors { errors };
-        tracing::error!("{err}");
-        return Err(err);
+    /// Skip files whose header declares no `hkaAnimation`-derived class
+    /// (see [`read_has_animation_class`]).
+    ///
+    /// Files the header check cannot classify are kept, so that loading them reports why.
+    pub animations_only: bool,
+}
+
+impl Default for CollectOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            ignore_files: true,
+            follow_symlinks: true,
+            animations_only: false,
+        }
     }
+}
+
+/// Files found by a collection, together with the paths that could not be read.
+///
+/// One missing input or unreadable subdirectory does not discard the other files.
+#[derive(Debug, Default)]
+pub struct CollectOutcome {
+    /// Collected files, sorted and deduplicated.
+    pub files: Vec<PathBuf>,
+    /// One error per input path, directory or entry that could not be read.
+    pub errors: Vec<CollectError>,
+}
+
+impl CollectOutcome {
+    /// Returns the files, or every error if any occurred.
+    ///
+    /// # Errors
+    /// [`CollectError::CollectErrors`] if any path could not be read.
+    pub fn into_result(self) -> Result<Vec<PathBuf>, CollectError> {
+        if self.errors.is_empty() {
+            Ok(self.files)
+        } else {
+            Err(CollectError::CollectErrors {
+                errors: self.errors,
+            })
+        }
+    }
+
+    fn from_error(err: CollectError) -> Self {
+        Self {
+            files: Vec::new(),
+            errors: vec![err],
+        }
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.files.extend(other.files);
+        self.errors.extend(other.errors);
+        self
+    }
+}
+
+impl From<Result<Vec<PathBuf>, CollectError>> for CollectOutcome {
+    fn from(result: Result<Vec<PathBuf>, CollectError>) -> Self {
+        match result {
+            Ok(files) => Self {
+                files,
+                errors: Vec::new(),
+            },
+            Err(err) => Self::from_error(err),
+        }
+    }
+}
+
+/// Collect all `.hkx`, `.xml` files from the given input paths (files or directories).
+///
+/// Unlike [`CollectOptions::default`], [`IGNORE_FILE_NAME`] files are not honored;
+/// use [`par_collect_hkx_files_with`] for that.
+pub fn par_collect_hkx_files(input_paths: Vec<PathBuf>) -> CollectOutcome {
+    let options = CollectOptions {
+        ignore_files: false,
+        ..Default::default()
+    };
+    let walker = Walker {
+        options: &options,
+        include: None,
+        exclude: GlobSet::empty(),
+    };
+    walker.collect(input_paths)
+}
+
+/// Collect `.hkx`, `.xml` files from the given input paths, filtered by `options`.
+///
+/// Input paths naming a file are collected if they match the globs, regardless of ignore files.
+///
+/// # Errors
+/// If a glob pattern is invalid. Unreadable paths are reported in [`CollectOutcome::errors`].
+pub fn par_collect_hkx_files_with(
+    input_paths: Vec<PathBuf>,
+    options: &CollectOptions,
+) -> Result<CollectOutcome, CollectError> {
+    Ok(Walker::new(options)?.collect(input_paths))
+}
 
-    let mut files = files.into_iter().flatten().collect::<Vec<_>>();
-    files.par_sort_unstable();
-    files.dedup();
-    Ok(files)
+/// [`CollectOptions`] with compiled globs.
+struct Walker<'a> {
+    options: &'a CollectOptions,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
 }
 
-fn collect_from_path(path: PathBuf) -> Result<Vec<PathBuf>, CollectError> {
-    if !path.exists() {
-        return PathNotFoundSnafu { path }.fail();
+impl<'a> Walker<'a> {
+    fn new(options: &'a CollectOptions) -> Result<Self, CollectError> {
+        let include = match options.include.as_slice() {
+            [] => None,
+            patterns => Some(glob_set(patterns)?),
+        };
+        Ok(Self {
+            options,
+            include,
+            exclude: glob_set(&options.exclude)?,
+        })
     }
 
-    if path.is_file() {
-        return Ok(is_hkx(&path).then(|| vec![path]).unwrap_or_default());
+    fn collect(&self, input_paths: Vec<PathBuf>) -> CollectOutcome {
+        let mut outcome = input_paths
+            .into_par_iter()
+            .map(|path| self.collect_from_path(path))
+            .reduce(CollectOutcome::default, CollectOutcome::merge);
+
+        if !outcome.errors.is_empty() {
+            let errors = outcome.errors.iter().map(ToString::to_string);
+            tracing::warn!(
+                "Errors during file collection:\n{}",
+                errors.collect::<Vec<_>>().join("\n")
+            );
+        }
+        outcome.files.par_sort_unstable();
+        outcome.files.dedup();
+        outcome
+    }
+
+    fn collect_from_path(&self, path: PathBuf) -> CollectOutcome {
+        if !path.exists() {
+            return CollectOutcome::from_error(CollectError::PathNotFound { path });
+        }
+
+        if path.is_file() {
+            let name = Path::new(path.file_name().unwrap_or_default());
+            return self
+                .is_selected(&path, name)
+                .map(|selected| if selected { vec![path] } else { Vec::new() })
+                .into();
+        }
+
+        if path.is_dir() {
+            return self.walk_dir(&path, &path, 0, &[], &[]);
+        }
+
+        CollectOutcome::default()
     }
 
-    if path.is_dir() {
-        let read_dir = fs::read_dir(&path).context(ReadDirSnafu { path: path.clone() })?;
+    /// Collects the files below `dir`, `depth` levels below `root`.
+    ///
+    /// `ignores` are the ignore files of the parent directories and `ancestors`
+    /// the canonical paths of the directories being walked (for loop detection).
+    fn walk_dir(
+        &self,
+        root: &Path,
+        dir: &Path,
+        depth: usize,
+        ignores: &[IgnoreFile],
+        ancestors: &[PathBuf],
+    ) -> CollectOutcome {
+        let mut ancestors = ancestors.to_vec();
+        if self.options.follow_symlinks {
+            let canonical = match fs::canonicalize(dir).context(ReadDirSnafu { path: dir }) {
+                Ok(canonical) => canonical,
+                Err(err) => return CollectOutcome::from_error(err),
+            };
+            if ancestors.contains(&canonical) {
+                tracing::warn!("Skipping symlink loop: {}", dir.display());
+                return CollectOutcome::default();
+            }
+            ancestors.push(canonical);
+        }
 
-        return read_dir
+        let mut ignores = ignores.to_vec();
+        let ignore_file = dir.join(IGNORE_FILE_NAME);
+        if self.options.ignore_files && ignore_file.is_file() {
+            match IgnoreFile::read(dir, &ignore_file) {
+                Ok(file) => ignores.push(file),
+                Err(err) => return CollectOutcome::from_error(err),
+            }
+        }
+
+        let read_dir = match fs::read_dir(dir).context(ReadDirSnafu { path: dir }) {
+            Ok(read_dir) => read_dir,
+            Err(err) => return CollectOutcome::from_error(err),
+        };
+        read_dir
             .par_bridge()
             .map(|entry| {
-                let entry = entry.context(ReadDirEntrySnafu)?;
-                collect_from_path(entry.path())
+                let entry = match entry.context(ReadDirEntrySnafu { path: dir }) {
+                    Ok(entry) => entry,
+                    Err(err) => return CollectOutcome::from_error(err),
+                };
+                self.visit_entry(root, &entry, depth, &ignores, &ancestors)
             })
-            .collect::<Result<Vec<Vec<PathBuf>>, CollectError>>()
-            .map(|lists| lists.into_iter().flatten().collect());
+            .reduce(CollectOutcome::default, CollectOutcome::merge)
+    }
+
+    /// Collects one directory entry of [`Self::walk_dir`].
+    fn visit_entry(
+        &self,
+        root: &Path,
+        entry: &fs::DirEntry,
+        depth: usize,
+        ignores: &[IgnoreFile],
+        ancestors: &[PathBuf],
+    ) -> CollectOutcome {
+        let path = entry.path();
+        let file_type = match entry.file_type().context(ReadDirEntrySnafu { path: &path }) {
+            Ok(file_type) => file_type,
+            Err(err) => return CollectOutcome::from_error(err),
+        };
+        let (is_dir, is_file) = match file_type.is_symlink() {
+            true if !self.options.follow_symlinks => return CollectOutcome::default(),
+            true => (path.is_dir(), path.is_file()),
+            false => (file_type.is_dir(), file_type.is_file()),
+        };
+
+        if is_ignored(ignores, &path, is_dir) {
+            return CollectOutcome::default();
+        }
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+
+        if is_dir {
+            let too_deep = self.options.max_depth.is_some_and(|max| depth >= max);
+            if too_deep || self.exclude.is_match(relative) {
+                return CollectOutcome::default();
+            }
+            self.walk_dir(root, &path, depth + 1, ignores, ancestors)
+        } else if is_file {
+            self.is_selected(&path, relative)
+                .map(|selected| {
+                    if selected {
+                        vec![path.clone()]
+                    } else {
+                        Vec::new()
+                    }
+                })
+                .into()
+        } else {
+            CollectOutcome::default()
+        }
     }
 
-    Ok(Vec::new())
+    /// Is the file at `path` (`relative` to the input directory) to be collected?
+    fn is_selected(&self, path: &Path, relative: &Path) -> Result<bool, CollectError> {
+        let matches = is_hkx(path)
+            && self
+                .include
+                .as_ref()
+                .is_none_or(|include| include.is_match(relative))
+            && !self.exclude.is_match(relative);
+        if !matches || !self.options.animations_only {
+            return Ok(matches);
+        }
+
+        let file = fs::File::open(path).context(ReadFileSnafu { path })?;
+        let found =
+            read_has_animation_class(io::BufReader::new(file)).context(ReadFileSnafu { path })?;
+        Ok(found != Some(false))
+    }
+}
+
+fn glob(pattern: &str) -> Result<Glob, CollectError> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .context(InvalidGlobSnafu { pattern })
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, CollectError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(glob(pattern)?);
+    }
+    builder.build().context(InvalidGlobSnafu {
+        pattern: patterns.join(", "),
+    })
+}
+
+/// One pattern of an ignore file.
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    matcher: GlobMatcher,
+    /// `!pattern`: re-include what earlier rules ignored.
+    negated: bool,
+    /// `pattern/`: only match directories.
+    dir_only: bool,
+}
+
+/// Parsed ignore file; patterns are relative to `base`.
+#[derive(Debug, Clone)]
+struct IgnoreFile {
+    base: PathBuf,
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreFile {
+    fn read(base: &Path, path: &Path) -> Result<Self, CollectError> {
+        let text = fs::read_to_string(path).context(ReadIgnoreFileSnafu { path })?;
+        Self::parse(base, &text)
+    }
+
+    fn parse(base: &Path, text: &str) -> Result<Self, CollectError> {
+        let mut rules = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, pattern) = match line.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, line),
+            };
+            let (dir_only, pattern) = match pattern.strip_suffix('/') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern),
+            };
+
+            // Like `.gitignore`, a pattern containing a slash is anchored to the ignore file's directory.
+            let pattern = match pattern.strip_prefix('/') {
+                Some(anchored) => anchored.to_string(),
+                None if pattern.contains('/') => pattern.to_string(),
+                None => format!("**/{pattern}"),
+            };
+            rules.push(IgnoreRule {
+                matcher: glob(&pattern)?.compile_matcher(),
+                negated,
+                dir_only,
+            });
+        }
+
+        Ok(Self {
+            base: base.to_path_buf(),
+            rules,
+        })
+    }
+}
+
+/// Applies every ignore file from the outermost directory inwards; the last matching rule wins.
+fn is_ignored(ignores: &[IgnoreFile], path: &Path, is_dir: bool) -> bool {
+    let mut ignored = false;
+    for file in ignores {
+        let Ok(relative) = path.strip_prefix(&file.base) else {
+            continue;
+        };
+        for rule in &file.rules {
+            if (is_dir || !rule.dir_only) && rule.matcher.is_match(relative) {
+                ignored = !rule.negated;
+            }
+        }
+    }
+    ignored
 }
 
 const ALLOWED_EXTENSIONS: &[&str] = &["hkx", "xml"];
@@ -75,10 +441,150 @@ pub enum CollectError {
         source: std::io::Error,
     },
 
-    #[snafu(display("Failed to read directory entry: {source}"))]
-    ReadDirEntry { source: std::io::Error },
+    #[snafu(display("Failed to read directory entry {}: {source}", path.display()))]
+    ReadDirEntry {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to read file {}: {source}", path.display()))]
+    ReadFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to read ignore file {}: {source}", path.display()))]
+    ReadIgnoreFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid glob pattern `{pattern}`: {source}"))]
+    InvalidGlob {
+        pattern: String,
+        source: globset::Error,
+    },
 
     /// Multiple errors during file collection
     #[snafu(display("Multiple errors during file collection:\n{}", errors.par_iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")))]
     CollectErrors { errors: Vec<CollectError> },
 }
+
+impl CollectError {
+    /// The file or directory the error is about, if it concerns a single one.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::PathNotFound { path }
+            | Self::ReadDir { path, .. }
+            | Self::ReadDirEntry { path, .. }
+            | Self::ReadFile { path, .. }
+            | Self::ReadIgnoreFile { path, .. } => Some(path),
+            Self::InvalidGlob { .. } | Self::CollectErrors { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignore_file_rules() {
+        let base = Path::new("mod");
+        let ignores = [IgnoreFile::parse(
+            base,
+            "# comment\n_1stperson/\n/behaviors\n*skeleton*.hkx\n!keep_skeleton.hkx\n",
+        )
+        .unwrap()];
+        let ignored = |path: &str, is_dir| is_ignored(&ignores, &base.join(path), is_dir);
+
+        assert!(ignored("meshes/_1stperson", true));
+        assert!(!ignored("meshes/_1stperson", false)); // directory-only rule
+        assert!(ignored("behaviors", true));
+        assert!(!ignored("meshes/behaviors", true)); // anchored rule
+        assert!(ignored("meshes/skeleton_female.hkx", false));
+        assert!(!ignored("meshes/keep_skeleton.hkx", false));
+        assert!(!ignored("meshes/idle.hkx", false));
+    }
+
+    #[test]
+    fn walks_with_depth_globs_and_ignore_file() {
+        let root = std::env::temp_dir().join(format!("hkanno_collect_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["a/b", "a/_1stperson", "skip"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "top.hkx",
+            "a/one.hkx",
+            "a/b/two.xml",
+            "a/_1stperson/fp.hkx",
+            "skip/x.hkx",
+            "a/note.txt",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        fs::write(root.join(IGNORE_FILE_NAME), "skip/\n").unwrap();
+
+        let collect = |options: &CollectOptions| {
+            let outcome = par_collect_hkx_files_with(vec![root.clone()], options).unwrap();
+            assert!(outcome.errors.is_empty());
+            outcome
+                .files
+                .iter()
+                .map(|path| {
+                    path.strip_prefix(&root)
+                        .unwrap()
+                        .to_string_lossy()
+                        .replace('\\', "/")
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let options = CollectOptions {
+            exclude: vec!["**/_1stperson/**".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(collect(&options), ["a/b/two.xml", "a/one.hkx", "top.hkx"]);
+
+        let options = CollectOptions {
+            max_depth: Some(1),
+            include: vec!["**/*.hkx".to_string()],
+            ignore_files: false,
+            ..Default::default()
+        };
+        assert_eq!(collect(&options), ["a/one.hkx", "skip/x.hkx", "top.hkx"]);
+
+        // Empty files cannot be classified and are kept; the skeleton is skipped.
+        fs::write(
+            root.join("a/b/two.xml"),
+            r##"<?xml version="1.0"?><hkobject name="#0001" class="hkaSkeleton">"##,
+        )
+        .unwrap();
+        let options = CollectOptions {
+            animations_only: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            collect(&options),
+            ["a/_1stperson/fp.hkx", "a/one.hkx", "top.hkx"]
+        );
+
+        let outcome = par_collect_hkx_files(vec![root.join("a"), root.join("missing")]);
+        assert_eq!(outcome.files.len(), 3);
+        assert!(matches!(
+            outcome.errors.as_slice(),
+            [CollectError::PathNotFound { .. }]
+        ));
+        assert_eq!(
+            outcome.errors[0].path(),
+            Some(root.join("missing").as_path())
+        );
+
+        // The legacy entry point does not honor ignore files.
+        let outcome = par_collect_hkx_files(vec![root.clone()]);
+        assert!(outcome.files.contains(&root.join("skip").join("x.hkx")));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,25 @@
+//! Content fingerprints shared by table exports and directory backups.
+
+/// Fingerprint of `bytes` (64-bit FNV-1a as 16 hex digits).
+///
+/// Not cryptographic: it only tells apart file contents or paths that differ.
+pub fn file_hash(bytes: &[u8]) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let hash = bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    });
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_hash_is_fnv1a() {
+        assert_eq!(file_hash(b""), "cbf29ce484222325");
+        assert_eq!(file_hash(b"a"), "af63dc4c8601ec8c");
+    }
+}
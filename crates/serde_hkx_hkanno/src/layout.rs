@@ -0,0 +1,338 @@
+//! Detection of the format an HKX file was saved in.
+//!
+//! A binary packfile starts with two magic words followed by its layout rules:
+//!
+//! ```txt
+//! offset  0: 57 E0 E0 57 10 C0 C0 10   magic
+//! offset  8: userTag (i32), fileVersion (i32)
+//! offset 16: bytesInPointer, littleEndian, reusePaddingOptimization, emptyBaseClassOptimization
+//! ```
+//!
+//! Skyrim LE animations use 4-byte pointers (`win32`), SE/AE use 8-byte pointers (`amd64`).
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Seek, SeekFrom},
+};
+
+use crate::OutFormat;
+
+const PACKFILE_MAGIC: [u8; 8] = [0x57, 0xE0, 0xE0, 0x57, 0x10, 0xC0, 0xC0, 0x10];
+const BYTES_IN_POINTER: usize = 16;
+const LITTLE_ENDIAN: usize = 17;
+
+/// Format of an existing HKX/XML file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum HkxFormat {
+    /// Little-endian packfile with 8-byte pointers (Skyrim SE/AE).
+    Amd64,
+    /// Little-endian packfile with 4-byte pointers (Skyrim LE).
+    Win32,
+    /// Havok XML packfile.
+    Xml,
+}
+
+impl HkxFormat {
+    /// Detects the format from the first bytes of a file.
+    ///
+    /// Returns `None` for big-endian packfiles, other pointer sizes and anything
+    /// that is neither a packfile nor XML.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&PACKFILE_MAGIC) {
+            return match (
+                bytes.get(BYTES_IN_POINTER).copied(),
+                bytes.get(LITTLE_ENDIAN).copied(),
+            ) {
+                (Some(8), Some(1)) => Some(Self::Amd64),
+                (Some(4), Some(1)) => Some(Self::Win32),
+                _ => None,
+            };
+        }
+
+        let text = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+        let start = text.iter().position(|b| !b.is_ascii_whitespace())?;
+        let text = &text[start..];
+        (text.starts_with(b"<?xml") || text.starts_with(b"<hkpackfile")).then_some(Self::Xml)
+    }
+
+    /// Returns the output format that writes files in this format.
+    pub const fn out_format(self) -> OutFormat {
+        match self {
+            Self::Amd64 => OutFormat::Amd64,
+            Self::Win32 => OutFormat::Win32,
+            Self::Xml => OutFormat::Xml,
+        }
+    }
+}
+
+/// `hkaAnimation` and the classes derived from it.
+const ANIMATION_CLASSES: &[&str] = &[
+    "hkaAnimation",
+    "hkaDeltaCompressedAnimation",
+    "hkaInterleavedUncompressedAnimation",
+    "hkaQuantizedAnimation",
+    "hkaSplineCompressedAnimation",
+    "hkaWaveletCompressedAnimation",
+];
+
+/// Checks whether a file declares an `hkaAnimation`-derived class, without deserializing it.
+///
+/// Packfiles are checked through their `__classnames__` section, XML files
+/// through the `class="..."` attributes of their objects.
+///
+/// Returns `None` if the format is not recognized or the packfile is truncated.
+pub fn has_animation_class(bytes: &[u8]) -> Option<bool> {
+    read_has_animation_class(io::Cursor::new(bytes))
+        .ok()
+        .flatten()
+}
+
+/// [`has_animation_class`] reading no more than it needs from `reader`.
+///
+/// Of a packfile only the header, the section headers and `__classnames__` are
+/// read; XML is scanned tag by tag and reading stops at the first animation object.
+///
+/// # Errors
+/// If reading fails for another reason than the file ending early.
+pub fn read_has_animation_class<R: BufRead + Seek>(mut reader: R) -> io::Result<Option<bool>> {
+    let mut probe = Vec::with_capacity(PROBE_LEN);
+    (&mut reader)
+        .take(PROBE_LEN as u64)
+        .read_to_end(&mut probe)?;
+    reader.rewind()?;
+
+    let found = match HkxFormat::detect(&probe) {
+        None => return Ok(None),
+        Some(HkxFormat::Xml) => xml_has_animation_class(reader).map(Some),
+        Some(HkxFormat::Amd64 | HkxFormat::Win32) => {
+            packfile_class_names(&mut reader).map(|names| {
+                names.map(|names| {
+                    names
+                        .iter()
+                        .any(|name| ANIMATION_CLASSES.contains(&name.as_str()))
+                })
+            })
+        }
+    };
+    match found {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        found => found,
+    }
+}
+
+/// How many leading bytes are enough for [`HkxFormat::detect`].
+const PROBE_LEN: usize = 1024;
+
+/// Scans the tags of an XML file for an object of an animation class.
+fn xml_has_animation_class(mut reader: impl BufRead) -> io::Result<bool> {
+    let mut tag = Vec::new();
+    loop {
+        tag.clear();
+        if reader.read_until(b'>', &mut tag)? == 0 {
+            return Ok(false);
+        }
+        if class_attribute(&tag).is_some_and(|class| {
+            ANIMATION_CLASSES
+                .iter()
+                .any(|name| name.as_bytes() == class)
+        }) {
+            return Ok(true);
+        }
+    }
+}
+
+/// The value of the first `class="..."` attribute in `tag`.
+fn class_attribute(tag: &[u8]) -> Option<&[u8]> {
+    const ATTRIBUTE: &[u8] = b"class=\"";
+    let start = tag.windows(ATTRIBUTE.len()).position(|w| w == ATTRIBUTE)? + ATTRIBUTE.len();
+    let len = tag[start..].iter().position(|&b| b == b'"')?;
+    Some(&tag[start..start + len])
+}
+
+/// Reads the class names listed in the `__classnames__` section of a little-endian packfile.
+///
+/// Returns `None` if there is no such section or it is malformed.
+fn packfile_class_names<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Vec<String>>> {
+    let u32_at = |bytes: &[u8], offset: usize| {
+        let word = [
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ];
+        u32::from_le_bytes(word) as usize
+    };
+
+    let mut header = [0; 0x40];
+    reader.read_exact(&mut header)?;
+    let file_version = u32_at(&header, 12);
+    let num_sections = u32_at(&header, 20);
+
+    // hk2014 (version 11) and later follow the header with a predicate array whose size
+    // is `m_predicateArraySizePlusPadding` (i16 at 0x3E), and use larger section headers.
+    let (header_size, section_header_size) = if file_version >= 11 {
+        let predicates = u16::from_le_bytes([header[0x3E], header[0x3F]]) as usize;
+        (0x40 + predicates, 0x40)
+    } else {
+        (0x40, 0x30)
+    };
+
+    for index in 0..num_sections {
+        let offset = header_size + index * section_header_size;
+        reader.seek(SeekFrom::Start(offset as u64))?;
+        let mut section = [0; 28];
+        reader.read_exact(&mut section)?;
+        if !section.starts_with(b"__classnames__\0") {
+            continue;
+        }
+
+        let (start, size) = (u32_at(&section, 20), u32_at(&section, 24));
+        reader.seek(SeekFrom::Start(start as u64))?;
+        let mut data = Vec::with_capacity(size.min(1 << 16));
+        reader.take(size as u64).read_to_end(&mut data)?;
+        if data.len() < size {
+            return Ok(None);
+        }
+
+        // Entries: u32 signature, 0x09, null-terminated name; padded with 0xFF.
+        let mut names = Vec::new();
+        let mut rest = data.as_slice();
+        while let [_, _, _, _, 0x09, tail @ ..] = rest {
+            let Some(end) = tail.iter().position(|&b| b == 0) else {
+                return Ok(None);
+            };
+            let Ok(name) = std::str::from_utf8(&tail[..end]) else {
+                return Ok(None);
+            };
+            names.push(name.to_string());
+            rest = &tail[end + 1..];
+        }
+        return Ok(Some(names));
+    }
+    Ok(None)
+}
+
+impl fmt::Display for HkxFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Amd64 => "amd64",
+            Self::Win32 => "win32",
+            Self::Xml => "xml",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packfile(bytes_in_pointer: u8, little_endian: u8) -> Vec<u8> {
+        let mut bytes = PACKFILE_MAGIC.to_vec();
+        bytes.extend([0; 8]); // userTag, fileVersion
+        bytes.extend([bytes_in_pointer, little_endian, 0, 1]);
+        bytes
+    }
+
+    #[test]
+    fn detects_packfile_layout() {
+        assert_eq!(HkxFormat::detect(&packfile(8, 1)), Some(HkxFormat::Amd64));
+        assert_eq!(HkxFormat::detect(&packfile(4, 1)), Some(HkxFormat::Win32));
+        assert_eq!(HkxFormat::detect(&packfile(4, 0)), None); // big-endian
+        assert_eq!(HkxFormat::detect(&PACKFILE_MAGIC), None); // truncated
+    }
+
+    /// Packfile of `file_version` whose only section is `__classnames__` listing `names`.
+    ///
+    /// Versions 11 (hk2014) and later get an 8-byte predicate array.
+    fn packfile_with_classes(file_version: u8, names: &[&str]) -> Vec<u8> {
+        let mut classnames = Vec::new();
+        for name in names {
+            classnames.extend([0, 0, 0, 0, 0x09]);
+            classnames.extend(name.as_bytes());
+            classnames.push(0);
+        }
+        classnames.resize(classnames.len().next_multiple_of(16), 0xFF);
+
+        let mut bytes = PACKFILE_MAGIC.to_vec();
+        bytes.extend([0, 0, 0, 0, file_version, 0, 0, 0]); // userTag, fileVersion
+        bytes.extend([8, 1, 0, 1]); // layout rules
+        bytes.extend(1_u32.to_le_bytes()); // numSections
+        let section_header_size = match file_version >= 11 {
+            true => {
+                bytes.resize(0x3C, 0);
+                bytes.extend((-1_i16).to_le_bytes()); // maxpredicate
+                bytes.extend(8_i16.to_le_bytes()); // predicateArraySizePlusPadding
+                bytes.resize(0x40 + 8, 0xFF); // predicate array
+                0x40
+            }
+            false => {
+                bytes.resize(0x40, 0);
+                0x30
+            }
+        };
+
+        let data_start = bytes.len() + section_header_size;
+        let mut tag = b"__classnames__".to_vec();
+        tag.resize(20, 0);
+        bytes.extend(tag);
+        bytes.extend((data_start as u32).to_le_bytes());
+        bytes.extend((classnames.len() as u32).to_le_bytes());
+        bytes.resize(data_start, 0);
+        bytes.extend(classnames);
+        bytes
+    }
+
+    #[test]
+    fn sniffs_packfile_class_names() {
+        for file_version in [8, 11] {
+            let animation =
+                packfile_with_classes(file_version, &["hkClass", "hkaSplineCompressedAnimation"]);
+            assert_eq!(has_animation_class(&animation), Some(true));
+
+            let skeleton = packfile_with_classes(
+                file_version,
+                &["hkClass", "hkaSkeleton", "hkaAnimationBinding"],
+            );
+            assert_eq!(has_animation_class(&skeleton), Some(false));
+
+            let truncated = &animation[..animation.len() - 8];
+            assert_eq!(has_animation_class(truncated), None);
+            assert_eq!(has_animation_class(&animation[..0x50]), None);
+        }
+    }
+
+    #[test]
+    fn sniffs_xml_classes() {
+        let xml = br##"<?xml version="1.0"?><hkobject name="#0003" class="hkaSplineCompressedAnimation">"##;
+        assert_eq!(has_animation_class(xml), Some(true));
+        let xml = br##"<?xml version="1.0"?><hkobject name="#0003" class="hkbBehaviorGraph">"##;
+        assert_eq!(has_animation_class(xml), Some(false));
+        assert_eq!(has_animation_class(b"not hkx"), None);
+    }
+
+    #[test]
+    fn stops_reading_at_the_first_animation_object() {
+        let mut xml = br##"<?xml version="1.0"?>
+<hkobject name="#0001" class="hkRootLevelContainer"></hkobject>
+<hkobject name="#0003" class="hkaSplineCompressedAnimation">"##
+            .to_vec();
+        let end = xml.len() as u64;
+        xml.extend(b"<hkparam name=\"data\">0 0 0 0</hkparam>".repeat(1000));
+
+        let mut reader = io::Cursor::new(xml);
+        assert_eq!(read_has_animation_class(&mut reader).unwrap(), Some(true));
+        assert_eq!(reader.position(), end);
+    }
+
+    #[test]
+    fn detects_xml() {
+        let xml = b"\xEF\xBB\xBF\r\n<?xml version=\"1.0\" encoding=\"ascii\"?>";
+        assert_eq!(HkxFormat::detect(xml), Some(HkxFormat::Xml));
+        assert_eq!(
+            HkxFormat::detect(b"<hkpackfile classversion=\"8\">"),
+            Some(HkxFormat::Xml)
+        );
+        assert_eq!(HkxFormat::detect(b"trackName: A"), None);
+        assert_eq!(HkxFormat::detect(b""), None);
+    }
+}
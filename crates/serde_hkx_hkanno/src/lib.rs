@@ -9,13 +9,17 @@
 //! ## Output Format (hkanno v2)
 //!
 //! ```text
-//! # numOriginalFrames: <usize>        <- hkaSplineCompressedAnimation.numFrames
+//! # ptr: #<usize>                     <- pointer of the hkaAnimation class (omitted when unknown)
+//! # hkanno v2                         <- format version marker
+//! # numOriginalFrames: <usize>        <- hkaSplineCompressedAnimation.numFrames (other classes: see `AnimationTiming`)
 //! # duration: <f32>                   <- hkaSplineCompressedAnimation.duration
 //! # numAnnotationTracks: <usize>      <- hkaSplineCompressedAnimation.annotationTracks.len()
+//! # fps: <f32>                        <- frame rate of `f<frame>` times (optional, default 30)
 //!
 //! trackName: <String>                 <- hkaAnnotationTrack.trackName
 //! # numAnnotations: <usize>           <- hkaAnnotationTrack.annotations.len()
 //! <time: f32> <text: StringPtr>       <- hkaAnnotationTrack.annotations[n].time, text
+//! f<frame: u32> <text: StringPtr>     <- time as a frame number (`<frame>f` is also accepted)
 //! <time: f32> <text: StringPtr>
 //! ...
 //!
@@ -28,6 +32,7 @@
 //! ## Sample
 //!
 //! ```txt
+//! # ptr: #0003
 //! # hkanno v2
 //! # numOriginalFrames: 38
 //! # duration: 1.5
@@ -47,9 +52,23 @@
 //! 0.250000 MCO_Step
 //! 0.900000 MCO_Land
 //! ```
+mod cst;
+mod diff;
+mod document;
 pub mod editor;
+mod event;
 pub mod file_collector;
+mod hash;
+mod layout;
+mod loaded;
 mod parser;
+mod pie;
+pub mod report;
+mod retime;
+pub mod table;
+mod timing;
+mod validate;
+mod verify;
 
 use havok_classes::Classes;
 use rayon::prelude::*;
@@ -57,7 +76,27 @@ use serde_hkx_features::ClassMap;
 use snafu::ResultExt as _;
 use std::{borrow::Cow, fmt, path::Path};
 
-pub use crate::parser::{parse_hkanno_str, HkannoParseError};
+pub use crate::cst::{update_hkanno_document_str, CstLine, CstLineKind, HkannoCst};
+pub use crate::diff::{
+    diff_documents, diff_hkanno, AnimationDiff, AnnotationChange, HkannoDiff, TrackChange,
+    TrackDiff,
+};
+pub use crate::document::{
+    parse_as_hkanno_document, parse_hkanno_document_borrowed, HkannoDocument,
+};
+pub use crate::event::{AnnotationEvent, Number, PayloadInstruction, PayloadPrefix};
+pub use crate::layout::{has_animation_class, read_has_animation_class, HkxFormat};
+pub use crate::loaded::LoadedHkx;
+pub use crate::parser::{
+    parse_hkanno_document_str, parse_hkanno_document_str_recovering, parse_hkanno_str,
+    parse_hkanno_str_recovering, HkannoParseError,
+};
+pub use crate::pie::{InstructionDef, InstructionRegistry, ParamDef, ParamKind, PayloadError};
+pub use crate::retime::{AnnotationFilter, Retime};
+pub use crate::timing::{
+    frame_at, frame_count, frame_time, is_valid_fps, AnimationTiming, DEFAULT_FPS,
+};
+pub use crate::validate::{Severity, ValidationIssue, ValidationMode, ValidationOptions};
 pub use serde_hkx_features::OutFormat;
 
 /// # hkanno module
@@ -70,14 +109,29 @@ pub use serde_hkx_features::OutFormat;
 ///
 /// This module supports both borrowed (`Cow::Borrowed`) and owned (`Cow::Owned`) data,
 /// enabling zero-copy extraction when possible.
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Hkanno<'a> {
-    /// `hkaSplineCompressedAnimation` index. e.g. `#0003`
+    /// Pointer of the target `hkaAnimation`-derived class. e.g. `#0003`
+    ///
+    /// `0` means unknown; writes then target the only animation of the file.
+    #[serde(default)]
     pub ptr: usize,
-    /// Number of frames in the original animation.
+    /// Number of frames in the original animation (`0`: not declared).
+    #[serde(default)]
     pub num_original_frames: i32,
-    /// Total duration (in seconds) of the animation.
+    /// Total duration (in seconds) of the animation (`0.0`: not declared).
+    #[serde(default)]
     pub duration: f32,
+    /// Track count declared by the `# numAnnotationTracks` header, if the text had one.
+    ///
+    /// This is informational only; the actual count is `annotation_tracks.len()`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_annotation_tracks: Option<usize>,
+    /// Frame rate declared by the `# fps` header, used for frame notation times (`f12`).
+    ///
+    /// `None` means [`DEFAULT_FPS`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fps: Option<f32>,
     /// A list of annotation tracks, each containing time–text pairs.
     pub annotation_tracks: Vec<AnnotationTrack<'a>>,
 }
@@ -89,11 +143,14 @@ impl<'a> Hkanno<'a> {
             ptr: self.ptr,
             num_original_frames: self.num_original_frames,
             duration: self.duration,
+            num_annotation_tracks: self.num_annotation_tracks,
+            fps: self.fps,
             annotation_tracks: self
                 .annotation_tracks
                 .into_par_iter()
                 .map(|track| AnnotationTrack {
                     track_name: track.track_name.map(|t| Cow::Owned(t.into_owned())),
+                    num_annotations: track.num_annotations,
                     annotations: track
                         .annotations
                         .into_par_iter()
@@ -112,24 +169,58 @@ impl<'a> Hkanno<'a> {
     /// # Errors
     /// If missing/multiple `hkaSplineCompressedAnimation`.
     pub fn write_to_classmap(self, class_map: &mut ClassMap<'a>) -> Result<(), HkannoError> {
-        use havok_types::StringPtr;
-
-        let mut animations: Vec<_> = class_map
-            .par_iter_mut()
-            .filter(|(_, class)| is_hka_animation_derived(class))
-            .collect();
-        let (_, animation_class) = {
+        let animation_class = if self.ptr == 0 {
+            let mut animations: Vec<_> = class_map
+                .par_iter_mut()
+                .filter(|(_, class)| is_hka_animation_derived(class))
+                .collect();
             match animations.len() {
                 0 => return MissingHkaAnimationClassSnafu.fail(),
-                1 => animations.swap_remove(0),
+                1 => animations.swap_remove(0).1,
                 _ => {
                     return Err(HkannoError::MultipleHkaAnimationFound {
                         count: animations.len(),
                     })
                 }
             }
+        } else {
+            class_map
+                .get_mut(&self.ptr)
+                .filter(|class| is_hka_animation_derived(class))
+                .ok_or(HkannoError::HkaAnimationNotFoundAt { ptr: self.ptr })?
         };
 
+        self.write_to_class(animation_class)
+    }
+
+    /// Returns the timing of the animation [`Self::write_to_classmap`] targets.
+    ///
+    /// # Errors
+    /// Same lookup errors as [`Self::write_to_classmap`].
+    pub(crate) fn target_timing(
+        &self,
+        class_map: &ClassMap<'_>,
+        fps: f32,
+    ) -> Result<AnimationTiming, HkannoError> {
+        if self.ptr == 0 {
+            return find_animation_timing(class_map, fps);
+        }
+        class_map
+            .get(&self.ptr)
+            .and_then(|class| AnimationTiming::of(class, fps))
+            .ok_or(HkannoError::HkaAnimationNotFoundAt { ptr: self.ptr })
+    }
+
+    /// Replaces the annotation tracks of one `hkaAnimation`-derived class with `self`'s.
+    ///
+    /// # Errors
+    /// If `animation_class` is not `hkaAnimation`-derived.
+    pub(crate) fn write_to_class(
+        self,
+        animation_class: &mut Classes<'a>,
+    ) -> Result<(), HkannoError> {
+        use havok_types::StringPtr;
+
         let (_num_original_frames, _duration, annotation_tracks) = match animation_class {
             Classes::hkaAnimation(class) => {
                 (&mut 0, &mut class.m_duration, &mut class.m_annotationTracks)
@@ -162,7 +253,7 @@ impl<'a> Hkanno<'a> {
             _ => return Err(HkannoError::MissingHkaAnimationClass),
         };
 
-        // User-provided values (especially from hkanno str, which may be replaced with 0 for comment purposes) cannot be trusted.
+        // User-provided values (hkanno header comments are hand-editable and default to 0 when omitted) cannot be trusted.
         // Therefore, the following should not be modified.
         // *num_original_frames = self.num_original_frames;
         // *duration = self.duration;
@@ -198,6 +289,7 @@ impl<'a> Hkanno<'a> {
     /// * `bytes` - Raw HKX or XML file bytes.
     /// * `format` - output format
     /// * `input` - The source file path (used only for error context and extension check).
+    /// * `validation` - How to check the annotations against the animation before writing.
     ///
     /// # Returns
     /// A new byte vector containing the updated HKX data.
@@ -205,6 +297,7 @@ impl<'a> Hkanno<'a> {
     /// # Errors
     /// Returns a [`HkannoError`] if:
     /// - Deserialization of the input bytes fails.
+    /// - Validation finds an error in [`ValidationMode::Strict`].
     /// - Annotation update fails.
     /// - Serialization of the updated data fails.
     pub fn update_hkx_bytes(
@@ -212,6 +305,7 @@ impl<'a> Hkanno<'a> {
         bytes: &mut Vec<u8>,
         format: OutFormat,
         input: &Path,
+        validation: &ValidationOptions,
     ) -> Result<Vec<u8>, HkannoError> {
         let mut text = String::new();
 
@@ -219,28 +313,85 @@ impl<'a> Hkanno<'a> {
         let mut class_map: ClassMap<'_> =
             serde_hkx_features::serde::de::deserialize(bytes, &mut text, input)
                 .context(SerdeHkxFeatureSnafu)?;
+
+        if validation.mode != ValidationMode::Off {
+            self.check(
+                self.target_timing(&class_map, validation.fps)?,
+                validation,
+                input,
+            )?;
+        }
+
         self.write_to_classmap(&mut class_map)?; // Update annotations (pure memory operation)
+        serialize_class_map(&mut class_map, format, input)
+    }
 
-        // Serialize back to bytes(NOTE: Binary data requires pre-sorting, so it is marked as &mut class_map.)
-        let updated_bytes = match format {
-            OutFormat::Amd64 | OutFormat::Win32 | OutFormat::Xml => {
-                serde_hkx_features::serde::ser::to_bytes(input, format, &mut class_map)
-            }
-            _ => unreachable!("This being called means a new format type has been created."),
+    /// Runs [`Self::validate`] and, if `validation.pie` is set, [`Self::validate_payloads`].
+    pub(crate) fn issues(
+        &self,
+        timing: AnimationTiming,
+        validation: &ValidationOptions,
+    ) -> Vec<ValidationIssue> {
+        let mut issues = self.validate(timing);
+        if let Some(registry) = &validation.pie {
+            issues.extend(self.validate_payloads(registry));
+        }
+        issues
+    }
+
+    /// Validates `self` against the target animation's timing, logging every issue.
+    ///
+    /// # Errors
+    /// In [`ValidationMode::Strict`], if any error-level issue is found.
+    pub(crate) fn check(
+        &self,
+        timing: AnimationTiming,
+        validation: &ValidationOptions,
+        input: &Path,
+    ) -> Result<(), HkannoError> {
+        let issues = self.issues(timing, validation);
+        for issue in &issues {
+            tracing::warn!("{}: {issue}", input.display());
         }
-        .context(SerdeHkxFeatureSnafu)?;
 
-        Ok(updated_bytes)
+        if validation.mode == ValidationMode::Strict
+            && issues.iter().any(|i| i.severity() == Severity::Error)
+        {
+            return Err(HkannoError::ValidationFailed { issues });
+        }
+        Ok(())
+    }
+}
+
+/// Serializes an edited `ClassMap` back to bytes in `format`.
+pub(crate) fn serialize_class_map(
+    class_map: &mut ClassMap<'_>,
+    format: OutFormat,
+    input: &Path,
+) -> Result<Vec<u8>, HkannoError> {
+    // Serialize back to bytes(NOTE: Binary data requires pre-sorting, so it is marked as &mut class_map.)
+    match format {
+        OutFormat::Amd64 | OutFormat::Win32 | OutFormat::Xml => {
+            serde_hkx_features::serde::ser::to_bytes(input, format, class_map)
+        }
+        _ => unreachable!("This being called means a new format type has been created."),
     }
+    .context(SerdeHkxFeatureSnafu)
 }
 
 /// Represents a single annotation track extracted from a Havok animation.
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct AnnotationTrack<'a> {
     /// The name of this annotation track (e.g. `PairedRoot`, `2_`, etc.).
     /// Corresponds to `hkaAnnotationTrack.trackName`.
     pub track_name: Option<Cow<'a, str>>,
 
+    /// Annotation count declared by the `# numAnnotations` comment, if the text had one.
+    ///
+    /// This is informational only; the actual count is `annotations.len()`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_annotations: Option<usize>,
+
     /// The collection of annotation entries in this track.
     pub annotations: Vec<Annotation<'a>>,
 }
@@ -249,7 +400,7 @@ pub struct AnnotationTrack<'a> {
 ///
 /// The `text` field uses `Cow<'a, str>` so that data may be borrowed
 /// directly from the parsed HKX data or owned after conversion.
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Annotation<'a> {
     /// The time (in seconds) at which this annotation occurs.
     pub time: f32,
@@ -257,16 +408,67 @@ pub struct Annotation<'a> {
     pub text: Option<Cow<'a, str>>,
 }
 
-impl<'a> fmt::Display for Hkanno<'a> {
+/// How [`Hkanno::display`] writes annotation times.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeNotation {
+    /// Seconds with 6 decimals, e.g. `0.400000`.
+    #[default]
+    Seconds,
+    /// Frame numbers at [`Hkanno::fps`], e.g. `f12`.
+    ///
+    /// Times between two frames are still written in seconds.
+    Frames,
+}
+
+impl Hkanno<'_> {
+    /// Formats `self` as hkanno text, writing times in `notation`.
+    ///
+    /// `to_string()` is the same as `display(TimeNotation::Seconds)`.
+    /// A frame-snapped time stays on the same frame when the text is parsed again.
+    pub fn display(&self, notation: TimeNotation) -> impl fmt::Display + '_ {
+        HkannoDisplay {
+            hkanno: self,
+            notation,
+        }
+    }
+}
+
+/// [`fmt::Display`] adapter returned by [`Hkanno::display`].
+struct HkannoDisplay<'h, 'a> {
+    hkanno: &'h Hkanno<'a>,
+    notation: TimeNotation,
+}
+
+impl fmt::Display for Hkanno<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.display(TimeNotation::Seconds).fmt(f)
+    }
+}
+
+impl fmt::Display for HkannoDisplay<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hkanno = self.hkanno;
+        let fps = hkanno.fps.unwrap_or(DEFAULT_FPS);
+
         // Header (global animation properties)
-        writeln!(f, "# numOriginalFrames: {}", self.num_original_frames)?;
-        writeln!(f, "# duration: {}", self.duration)?;
-        writeln!(f, "# numAnnotationTracks: {}", self.annotation_tracks.len())?;
+        if hkanno.ptr != 0 {
+            writeln!(f, "# ptr: #{:04}", hkanno.ptr)?;
+        }
+        writeln!(f, "# hkanno v2")?;
+        writeln!(f, "# numOriginalFrames: {}", hkanno.num_original_frames)?;
+        writeln!(f, "# duration: {}", hkanno.duration)?;
+        writeln!(
+            f,
+            "# numAnnotationTracks: {}",
+            hkanno.annotation_tracks.len()
+        )?;
+        if hkanno.fps.is_some() || self.notation == TimeNotation::Frames {
+            writeln!(f, "# fps: {fps}")?;
+        }
         writeln!(f)?;
 
         // Each track block
-        for track in &self.annotation_tracks {
+        for track in &hkanno.annotation_tracks {
             writeln!(
                 f,
                 "trackName: {}",
@@ -276,7 +478,13 @@ impl<'a> fmt::Display for Hkanno<'a> {
 
             for ann in &track.annotations {
                 let text = ann.text.as_deref().unwrap_or(havok_types::NULL_STR);
-                writeln!(f, "{:.6} {}", ann.time, text)?;
+                match self.notation {
+                    TimeNotation::Frames => match frame_at(ann.time, fps) {
+                        Some(frame) => writeln!(f, "f{frame} {text}")?,
+                        None => writeln!(f, "{:.6} {text}", ann.time)?,
+                    },
+                    TimeNotation::Seconds => writeln!(f, "{:.6} {text}", ann.time)?,
+                }
             }
 
             // Separate tracks by one blank line
@@ -299,6 +507,7 @@ impl<'a> fmt::Display for Hkanno<'a> {
 /// - If no spline is found, returns [`HkannoError::MissingSpline`].
 /// - If multiple splines are found, returns [`HkannoError::MultipleSplinesFound`] with the count.
 /// - Each annotation track and annotation is converted into [`AnnotationTrack`] and [`Annotation`] structures.
+/// - `num_original_frames` is derived as described in [`AnimationTiming::of`], using `fps`.
 ///
 /// # Errors
 ///
@@ -307,9 +516,10 @@ impl<'a> fmt::Display for Hkanno<'a> {
 /// - [`HkannoError::MissingSpline`] – no spline found in the `ClassMap`.
 /// - [`HkannoError::MultipleSplinesFound`] – more than one spline found.
 /// - [`HkannoError::UnsupportedI32Variant`] – the number-of-frames field is an unsupported variant (`EventId` or `VariableId`).
-pub fn parse_hkanno_borrowed<'a>(class_map: ClassMap<'a>) -> Result<Hkanno<'a>, HkannoError> {
-    use havok_classes::Classes;
-
+pub fn parse_hkanno_borrowed<'a>(
+    class_map: ClassMap<'a>,
+    fps: f32,
+) -> Result<Hkanno<'a>, HkannoError> {
     // Find the one `hkaAnimation`
     let (ptr, animation_class) = {
         // Find C++ classes that inherit from `hkaAnimation` C++
@@ -329,38 +539,30 @@ pub fn parse_hkanno_borrowed<'a>(class_map: ClassMap<'a>) -> Result<Hkanno<'a>,
         }
     };
 
-    const FPS: f32 = 30.0;
-    let (num_original_frames, duration, annotation_tracks): (f32, _, _) = match animation_class {
-        Classes::hkaAnimation(class) => (
-            class.m_duration * FPS,
-            class.m_duration,
-            class.m_annotationTracks,
-        ),
-        Classes::hkaDeltaCompressedAnimation(class) => (
-            class.parent.m_duration * FPS,
-            class.parent.m_duration,
-            class.parent.m_annotationTracks,
-        ),
-        Classes::hkaInterleavedUncompressedAnimation(class) => (
-            class.parent.m_duration * FPS,
-            class.parent.m_duration,
-            class.parent.m_annotationTracks,
-        ),
-        Classes::hkaQuantizedAnimation(class) => (
-            class.parent.m_duration * FPS,
-            class.parent.m_duration,
-            class.parent.m_annotationTracks,
-        ),
-        Classes::hkaSplineCompressedAnimation(class) => (
-            class.m_numFrames as f32,
-            class.parent.m_duration,
-            class.parent.m_annotationTracks,
-        ),
-        Classes::hkaWaveletCompressedAnimation(class) => (
-            class.parent.m_duration * FPS,
-            class.parent.m_duration,
-            class.parent.m_annotationTracks,
-        ),
+    hkanno_from_class(ptr, animation_class, fps)
+}
+
+/// Extracts the annotations of one `hkaAnimation`-derived class found at `ptr`.
+///
+/// # Errors
+/// If `animation_class` is not `hkaAnimation`-derived.
+pub(crate) fn hkanno_from_class(
+    ptr: usize,
+    animation_class: Classes<'_>,
+    fps: f32,
+) -> Result<Hkanno<'_>, HkannoError> {
+    let AnimationTiming {
+        duration,
+        num_frames: num_original_frames,
+        ..
+    } = AnimationTiming::of(&animation_class, fps).ok_or(HkannoError::MissingHkaAnimationClass)?;
+    let annotation_tracks = match animation_class {
+        Classes::hkaAnimation(class) => class.m_annotationTracks,
+        Classes::hkaDeltaCompressedAnimation(class) => class.parent.m_annotationTracks,
+        Classes::hkaInterleavedUncompressedAnimation(class) => class.parent.m_annotationTracks,
+        Classes::hkaQuantizedAnimation(class) => class.parent.m_annotationTracks,
+        Classes::hkaSplineCompressedAnimation(class) => class.parent.m_annotationTracks,
+        Classes::hkaWaveletCompressedAnimation(class) => class.parent.m_annotationTracks,
         _ => return Err(HkannoError::MissingHkaAnimationClass),
     };
 
@@ -377,6 +579,7 @@ pub fn parse_hkanno_borrowed<'a>(class_map: ClassMap<'a>) -> Result<Hkanno<'a>,
                 .collect::<Vec<_>>();
             AnnotationTrack {
                 track_name: track.m_trackName.into_inner(),
+                num_annotations: None,
                 annotations,
             }
         })
@@ -384,14 +587,36 @@ pub fn parse_hkanno_borrowed<'a>(class_map: ClassMap<'a>) -> Result<Hkanno<'a>,
 
     Ok(Hkanno {
         ptr,
-        num_original_frames: (num_original_frames + 1.0) as i32,
+        num_original_frames,
         duration,
+        num_annotation_tracks: None,
+        // Only a non-default rate needs a `# fps` header.
+        fps: (fps != DEFAULT_FPS).then_some(fps),
         annotation_tracks: tracks,
     })
 }
 
+/// Finds the one `hkaAnimation`-derived class and returns its timing at `fps`.
+///
+/// # Errors
+/// If missing/multiple `hkaAnimation`-derived classes.
+pub(crate) fn find_animation_timing(
+    class_map: &ClassMap<'_>,
+    fps: f32,
+) -> Result<AnimationTiming, HkannoError> {
+    let mut timings: Vec<_> = class_map
+        .par_iter()
+        .filter_map(|(_, class)| AnimationTiming::of(class, fps))
+        .collect();
+    match timings.len() {
+        0 => MissingHkaAnimationClassSnafu.fail(),
+        1 => Ok(timings.swap_remove(0)),
+        count => Err(HkannoError::MultipleHkaAnimationFound { count }),
+    }
+}
+
 /// Does this class inherit from `hkaAnimation`?
-fn is_hka_animation_derived(class: &Classes<'_>) -> bool {
+pub(crate) fn is_hka_animation_derived(class: &Classes<'_>) -> bool {
     matches!(
         class,
         Classes::hkaAnimation(_)
@@ -436,7 +661,7 @@ fn is_hka_animation_derived(class: &Classes<'_>) -> bool {
 /// use std::path::Path;
 /// use std::error::Error;
 ///
-/// use serde_hkx_for_gui::hkanno::{parse_as_hkanno, HkannoError};
+/// use serde_hkx_for_gui::hkanno::{parse_as_hkanno, HkannoError, DEFAULT_FPS};
 ///
 /// fn example() -> Result<(), Box<dyn Error>> {
 ///     let path = Path::new("example.hkx"); // or xml(from hkx)
@@ -444,7 +669,7 @@ fn is_hka_animation_derived(class: &Classes<'_>) -> bool {
 ///     let mut buffer = String::new(); // To avoid ownership error xml receiver.
 ///
 ///     // parse_as_hkanno returns Result<Hkanno, HkannoError>
-///     let hkanno = parse_as_hkanno(&bytes, &mut buffer, path)?;
+///     let hkanno = parse_as_hkanno(&bytes, &mut buffer, path, DEFAULT_FPS)?;
 ///
 ///     println!("Number of frames: {}", hkanno.num_original_frames);
 ///     Ok(())
@@ -454,10 +679,11 @@ pub fn parse_as_hkanno<'a>(
     bytes: &'a Vec<u8>,
     text: &'a mut String,
     path: &Path,
+    fps: f32,
 ) -> Result<Hkanno<'a>, HkannoError> {
     let class_map: ClassMap<'a> = serde_hkx_features::serde::de::deserialize(bytes, text, path)
         .context(SerdeHkxFeatureSnafu)?;
-    parse_hkanno_borrowed(class_map)
+    parse_hkanno_borrowed(class_map, fps)
 }
 
 /// Custom error type for hkanno parsing operations.
@@ -475,10 +701,24 @@ pub enum HkannoError {
     /// expected one `hkaAnimation` per `hkx`, but multiple were obtained. Got count: {count}
     MultipleHkaAnimationFound { count: usize },
 
+    /// The hkanno `# ptr:` no longer refers to an `hkaAnimation`-derived class.
+    #[snafu(display(
+        "`# ptr: #{ptr:04}` does not refer to an `hkaAnimation`-derived class (was the HKX changed after dumping?)"
+    ))]
+    HkaAnimationNotFoundAt { ptr: usize },
+
+    /// The same animation pointer has more than one hkanno section.
+    #[snafu(display("duplicate hkanno section for `#{ptr:04}`"))]
+    DuplicateHkannoSection { ptr: usize },
+
     /// Raised when an unsupported I32 variant was encountered.
     #[snafu(display("Unsupported i32 in animation field: {variant}"))]
     UnsupportedI32Variant { variant: String },
 
+    /// Raised when the HKX/XML bytes could not be deserialized.
+    #[snafu(display("failed to deserialize: {message}"))]
+    DeserializeFailed { message: String },
+
     /// Raised when file IO fails.
     #[snafu(display("Failed to Read/Write. path: {}, err: {source}", path.display()))]
     IoError {
@@ -498,18 +738,134 @@ pub enum HkannoError {
     #[snafu(transparent)]
     DeError { source: HkannoParseError },
 
-    /// Unsupported output format: {format}. Expected: `amd64`, `win32`, `xml`.
+    /// Annotations failed validation against the target animation.
+    #[snafu(display("hkanno validation failed:\n{}", issues.iter().map(|i| format!("- {i}")).collect::<Vec<_>>().join("\n")))]
+    ValidationFailed { issues: Vec<ValidationIssue> },
+
+    /// An edit referred to a track or annotation that does not exist.
+    #[snafu(display("{}", match index {
+        Some(index) => format!("annotation track[{track}][{index}] does not exist"),
+        None => format!("annotation track[{track}] does not exist"),
+    }))]
+    AnnotationNotFound { track: usize, index: Option<usize> },
+
+    /// Tracks or annotations were added or removed, so the text cannot be edited in place.
+    CstLayoutChanged,
+
+    /// A PIE instruction definition file could not be parsed.
+    #[snafu(display("invalid PIE instruction file: {message}"))]
+    InvalidInstructionFile { message: String },
+
+    /// A JSON/YAML annotation document could not be parsed.
+    #[snafu(display("invalid {format} annotation document: {message}"))]
+    InvalidStructuredDocument {
+        format: editor::StructuredFormat,
+        message: String,
+    },
+
+    /// An annotation table could not be read or written.
+    #[snafu(display("annotation table: {message}"))]
+    AnnotationTable { message: String },
+
+    /// The file was modified after its annotations were exported, so importing them would discard the change.
+    #[snafu(display("file changed since the table was exported (hash {exported}, now {actual}); export it again"))]
+    FileChangedSinceExport { exported: String, actual: String },
+
+    /// An event-name pattern of an [`AnnotationFilter`] is not a valid glob.
+    #[snafu(display("invalid event pattern `{pattern}`: {source}"))]
+    InvalidEventPattern {
+        pattern: String,
+        source: globset::Error,
+    },
+
+    /// A [`Retime`] cannot be applied.
+    #[snafu(display("cannot retime annotations: {reason}"))]
+    InvalidRetime { reason: String },
+
+    /// Unsupported output format: {format}. Expected: `amd64`, `win32`, `xml`, `same`.
     InvalidOutputFormat { format: String },
 
+    /// The written output does not hold what was meant to be written.
+    #[snafu(display("output verification failed at `#{ptr:04}` ({class}): {reason}"))]
+    OutputVerificationFailed {
+        ptr: usize,
+        class: String,
+        reason: String,
+    },
+
+    /// The written output has a different number of classes than the input. expected: {expected}, actual: {actual}
+    OutputClassCountMismatch { expected: usize, actual: usize },
+
+    /// Cannot detect the format of the input file to save it the same way. Expected a little-endian packfile or XML.
+    UnknownInputFormat,
+
     #[snafu(transparent)]
     Utf8Error { source: std::string::FromUtf8Error },
 }
 
+impl HkannoError {
+    /// Formats the error of processing the hkanno text `content` read from `origin`.
+    ///
+    /// A syntax error reports every broken line of `content` at `origin:line:column`,
+    /// not just the first; other errors are formatted as they are.
+    pub fn render(&self, origin: &Path, content: &str) -> String {
+        let Self::DeError { source } = self else {
+            return self.to_string();
+        };
+        let origin = origin.display().to_string();
+        let (_, errors) = parse_hkanno_document_str_recovering(content);
+        match errors.is_empty() {
+            // The recovering parser accepts more than the strict one; keep the original error.
+            true => source.render(&origin),
+            false => errors
+                .iter()
+                .map(|e| e.render(&origin))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::{borrow::Cow, path::Path};
 
+    #[test]
+    fn dump_declares_only_a_non_default_fps() {
+        let class = || {
+            Classes::hkaAnimation(
+                havok_classes::hkaAnimation {
+                    m_duration: 1.0,
+                    ..Default::default()
+                }
+                .into(),
+            )
+        };
+        let default = hkanno_from_class(1, class(), DEFAULT_FPS).unwrap();
+        assert_eq!(default.fps, None);
+        assert!(!default.to_string().contains("# fps"));
+
+        let sixty = hkanno_from_class(1, class(), 60.0).unwrap();
+        assert_eq!((sixty.fps, sixty.num_original_frames), (Some(60.0), 61));
+    }
+
+    #[test]
+    fn render_reports_every_syntax_error_or_falls_back() {
+        let content = "trackName: T\nabc\n0.1 ok\nxyz\n";
+        let err = HkannoError::from(parse_hkanno_document_str(content).unwrap_err());
+        let rendered = err.render(Path::new("idle.txt"), content);
+        assert!(rendered.starts_with("idle.txt:2:1: "));
+        assert!(rendered.contains("\nidle.txt:4:1: "));
+
+        // The content no longer shows the error: the original one is kept.
+        let rendered = err.render(Path::new("idle.txt"), "");
+        assert!(rendered.starts_with("idle.txt:2:1: "));
+
+        let err = HkannoError::MissingHkaAnimationClass;
+        assert_eq!(err.render(Path::new("idle.txt"), content), err.to_string());
+    }
+
     #[test]
     fn test_hkanno_to_string_format() {
         // Dummy Hkanno with 2 tracks and optional text
@@ -517,9 +873,12 @@ mod tests {
             ptr: 3,
             num_original_frames: 10,
             duration: 0.8,
+            num_annotation_tracks: None,
+            fps: None,
             annotation_tracks: vec![
                 AnnotationTrack {
                     track_name: Some(Cow::Borrowed("Track1")),
+                    num_annotations: None,
                     annotations: vec![
                         Annotation {
                             time: 0.1,
@@ -533,6 +892,7 @@ mod tests {
                 },
                 AnnotationTrack {
                     track_name: Some(Cow::Borrowed("Track2")),
+                    num_annotations: None,
                     annotations: vec![
                         Annotation {
                             time: 0.3,
@@ -552,6 +912,7 @@ mod tests {
         let output = hkanno.to_string();
 
         // Header checks
+        assert!(output.starts_with("# ptr: #0003\n# hkanno v2\n"));
         assert!(output.contains("# numOriginalFrames: 10"));
         assert!(output.contains("# duration: 0.8"));
         assert!(output.contains("# numAnnotationTracks: 2"));
@@ -576,7 +937,7 @@ mod tests {
         let bytes = std::fs::read(path).expect("Failed to read test HKX file");
         let mut buffer = String::new();
 
-        let hkanno = parse_as_hkanno(&bytes, &mut buffer, Path::new(path))
+        let hkanno = parse_as_hkanno(&bytes, &mut buffer, Path::new(path), DEFAULT_FPS)
             .expect("Failed to parse HKX file as Hkanno");
 
         dbg!(&hkanno);
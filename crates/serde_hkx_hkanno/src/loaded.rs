@@ -0,0 +1,230 @@
+//! HKX files loaded once and reused.
+//!
+//! Dumping, reviewing, previewing and applying annotations all start from the
+//! file's `ClassMap`. Deserializing it dominates the runtime of large batches, so
+//! [`LoadedHkx`] keeps the file bytes together with the classes borrowing from
+//! them; annotations can then be extracted and applied any number of times, e.g.
+//! from a cache kept in GUI state between commands.
+use serde_hkx_features::ClassMap;
+use snafu::ResultExt as _;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+use crate::{
+    is_hka_animation_derived, parse_hkanno_document_borrowed, serialize_class_map, HkannoDocument,
+    HkannoError, HkxFormat, IoSnafu, OutFormat, SerdeHkxFeatureSnafu, ValidationIssue,
+    ValidationOptions,
+};
+
+/// File contents the class map borrows from.
+struct Source {
+    bytes: Vec<u8>,
+    /// Decoded text of an XML file.
+    xml: Option<String>,
+}
+
+self_cell::self_cell!(
+    struct LoadedClasses {
+        owner: Source,
+
+        #[covariant]
+        dependent: ClassMap,
+    }
+);
+
+/// An HKX or XML file deserialized once.
+///
+/// Every operation works on a copy of the classes, so the handle always reflects
+/// the file as it was loaded and can be shared between threads.
+pub struct LoadedHkx {
+    path: PathBuf,
+    format: Option<HkxFormat>,
+    classes: LoadedClasses,
+}
+
+impl LoadedHkx {
+    /// Reads and deserializes the file at `path`.
+    ///
+    /// # Errors
+    /// If reading or deserializing the file fails.
+    pub async fn open(path: &Path) -> Result<Self, HkannoError> {
+        let bytes = fs::read(path).await.with_context(|_| IoSnafu { path })?;
+        Self::from_bytes(bytes, path)
+    }
+
+    /// Deserializes the contents of an HKX or XML file.
+    ///
+    /// `path` is only used to label errors and output.
+    ///
+    /// # Errors
+    /// If the bytes are neither a valid packfile nor valid XML.
+    pub fn from_bytes(bytes: Vec<u8>, path: impl Into<PathBuf>) -> Result<Self, HkannoError> {
+        let path = path.into();
+        let format = HkxFormat::detect(&bytes);
+
+        let load = || {
+            let xml = match format {
+                Some(HkxFormat::Xml) => {
+                    let text = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+                    Some(String::from_utf8(text.to_vec())?)
+                }
+                Some(HkxFormat::Amd64 | HkxFormat::Win32) => None,
+                // Unrecognized header (e.g. XML with a UTF-16 BOM or a leading comment):
+                // let the extension-based deserializer decide, and keep the text it decoded.
+                None => {
+                    let mut text = String::new();
+                    serde_hkx_features::serde::de::deserialize(&bytes, &mut text, &path)
+                        .context(SerdeHkxFeatureSnafu)?;
+                    (!text.is_empty()).then_some(text)
+                }
+            };
+            LoadedClasses::try_new(Source { bytes, xml }, |source| {
+                match &source.xml {
+                    Some(xml) => serde_hkx::from_str(xml),
+                    None => serde_hkx::from_bytes(&source.bytes),
+                }
+                .map_err(|e| HkannoError::DeserializeFailed {
+                    message: e.to_string(),
+                })
+            })
+        };
+        let classes = load().map_err(|e| HkannoError::HkxError {
+            source: Box::new(e),
+            path: path.clone(),
+        })?;
+
+        Ok(Self {
+            path,
+            format,
+            classes,
+        })
+    }
+
+    /// Path the file was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// File contents the classes were deserialized from.
+    pub fn bytes(&self) -> &[u8] {
+        &self.classes.borrow_owner().bytes
+    }
+
+    /// Format detected from the file header (`None` if not recognized).
+    pub const fn format(&self) -> Option<HkxFormat> {
+        self.format
+    }
+
+    /// Returns the deserialized classes.
+    pub fn class_map(&self) -> &ClassMap<'_> {
+        self.classes.borrow_dependent()
+    }
+
+    /// Extracts the annotations of every `hkaAnimation`-derived class.
+    ///
+    /// # Errors
+    /// Same as [`parse_hkanno_document_borrowed`].
+    pub fn extract(&self, fps: f32) -> Result<HkannoDocument<'static>, HkannoError> {
+        let animations: ClassMap<'_> = self
+            .class_map()
+            .iter()
+            .filter(|(_, class)| is_hka_animation_derived(class))
+            .map(|(&ptr, class)| (ptr, class.clone()))
+            .collect();
+        parse_hkanno_document_borrowed(animations, fps).map(HkannoDocument::into_static)
+    }
+
+    /// Checks every section of `document` against the animation it targets.
+    ///
+    /// Returns the issues of each section together with its [`Hkanno::ptr`](crate::Hkanno::ptr).
+    /// Nothing is logged and `validation.mode` is ignored.
+    ///
+    /// # Errors
+    /// If a section's pointer does not refer to an `hkaAnimation`-derived class.
+    pub fn validate(
+        &self,
+        document: &HkannoDocument<'_>,
+        validation: &ValidationOptions,
+    ) -> Result<Vec<(usize, ValidationIssue)>, HkannoError> {
+        let mut issues = Vec::new();
+        for hkanno in &document.animations {
+            let timing = hkanno.target_timing(self.class_map(), validation.fps)?;
+            issues.extend(
+                hkanno
+                    .issues(timing, validation)
+                    .into_iter()
+                    .map(|issue| (hkanno.ptr, issue)),
+            );
+        }
+        Ok(issues)
+    }
+
+    /// Serializes the classes with `document` written into them.
+    ///
+    /// With `verify`, the output is deserialized again and must hold the
+    /// requested annotations and otherwise unchanged classes.
+    ///
+    /// # Errors
+    /// Same as [`HkannoDocument::update_hkx_bytes`], plus the verification errors.
+    pub fn apply<'a>(
+        &'a self,
+        document: HkannoDocument<'a>,
+        format: OutFormat,
+        validation: &ValidationOptions,
+        verify: bool,
+    ) -> Result<Vec<u8>, HkannoError> {
+        let mut class_map = self.class_map().clone();
+        let original = verify.then(|| self.class_map());
+        document.update_class_map(&mut class_map, format, &self.path, validation, original)
+    }
+
+    /// Serializes the classes unchanged, e.g. to convert the file to XML.
+    ///
+    /// # Errors
+    /// If serialization fails.
+    pub fn serialize(&self, format: OutFormat) -> Result<Vec<u8>, HkannoError> {
+        let mut class_map = self.class_map().clone();
+        serialize_class_map(&mut class_map, format, &self.path)
+    }
+}
+
+impl fmt::Debug for LoadedHkx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadedHkx")
+            .field("path", &self.path)
+            .field("format", &self.format)
+            .field("classes", &self.class_map().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_name_the_file() {
+        let err = LoadedHkx::from_bytes(b"not a packfile".to_vec(), "a.hkx").unwrap_err();
+        assert!(
+            matches!(&err, HkannoError::HkxError { path, .. } if path == Path::new("a.hkx")),
+            "{err:?}"
+        );
+
+        let xml = b"<?xml version=\"1.0\"?>\n<hkpackfile>\xFF</hkpackfile>".to_vec();
+        let err = LoadedHkx::from_bytes(xml, "a.xml").unwrap_err();
+        assert!(
+            matches!(&err, HkannoError::HkxError { path, .. } if path == Path::new("a.xml")),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn open_reports_missing_files() {
+        let path = std::env::temp_dir().join(format!("hkanno_missing_{}.hkx", std::process::id()));
+        let err = LoadedHkx::open(&path).await.unwrap_err();
+        assert!(matches!(err, HkannoError::IoError { .. }), "{err:?}");
+    }
+}
@@ -1,13 +1,18 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, ops::Range};
 
 use havok_types::NULL_STR;
 use winnow::{
-    ascii::{float, line_ending, space0, space1, till_line_ending, Caseless},
-    combinator::{opt, preceded, repeat},
+    ascii::{
+        dec_int, dec_uint, digit1, float, line_ending, space0, space1, till_line_ending, Caseless,
+    },
+    combinator::{alt, cut_err, eof, opt, preceded, repeat, terminated},
+    error::{ContextError, ErrMode, StrContext, StrContextValue},
     ModalResult, Parser as _,
 };
 
-use crate::{Annotation, AnnotationTrack, Hkanno};
+use crate::{
+    frame_time, is_valid_fps, Annotation, AnnotationTrack, Hkanno, HkannoDocument, DEFAULT_FPS,
+};
 
 /// Error type returned when parsing hkanno text fails.
 ///
@@ -15,34 +20,118 @@ use crate::{Annotation, AnnotationTrack, Hkanno};
 /// such as unexpected tokens, malformed numbers, or incomplete lines.
 ///
 /// It does **not** represent semantic validation errors (e.g. mismatched
-/// counts), which are intentionally not enforced by this parser.
-#[derive(Debug)]
+/// counts), which are not enforced by this parser; see [`Hkanno::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct HkannoParseError {
-    /// Human-readable description of the parse failure.
-    pub message: String,
+    /// 1-based line number of the failure.
+    pub line: usize,
+    /// 1-based column (in characters) of the failure.
+    pub column: usize,
+    /// Byte range of the offending token within the parsed input.
+    pub span: Range<usize>,
+    /// The full text of the offending line (without line ending).
+    pub line_text: String,
+    /// What the parser expected at this position. e.g. `["track name line", "float time"]`
+    pub expected: Vec<&'static str>,
+}
+
+impl HkannoParseError {
+    /// Builds an error pointing at byte `offset` of `input`.
+    fn new(input: &str, offset: usize, expected: Vec<&'static str>) -> Self {
+        let offset = offset.min(input.len());
+        let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[offset..]
+            .find('\n')
+            .map_or(input.len(), |i| offset + i);
+        let line_text = input[line_start..line_end].trim_end_matches('\r');
+
+        let token_len = input[offset..line_start + line_text.len()]
+            .find(char::is_whitespace)
+            .unwrap_or(line_start + line_text.len() - offset);
+
+        Self {
+            line: input[..offset].matches('\n').count() + 1,
+            column: input[line_start..offset].chars().count() + 1,
+            span: offset..offset + token_len,
+            line_text: line_text.to_string(),
+            expected,
+        }
+    }
+
+    /// Re-points an error from a section of `input` starting at byte `base` to `input` itself.
+    pub(crate) fn rebase(self, input: &str, base: usize) -> Self {
+        Self::new(input, base + self.span.start, self.expected)
+    }
+
+    /// Renders the error prefixed with `origin` (typically a file path),
+    /// e.g. `attack.hkx:14:3: expected float time`, followed by a caret snippet.
+    pub fn render(&self, origin: &str) -> String {
+        format!("{origin}:{self}")
+    }
+
+    /// Renders the offending line with a caret under the failing token.
+    ///
+    /// ```text
+    ///    |
+    /// 14 | abc text
+    ///    | ^^^
+    /// ```
+    ///
+    /// Carets are counted in characters and tabs before the token are kept, so they
+    /// line up under non-ASCII text and tab-separated times.
+    pub fn snippet(&self) -> String {
+        let line_no = self.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+        let mut chars = self.line_text.chars();
+        let indent: String = chars
+            .by_ref()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let token_chars = chars.take_while(|c| !c.is_whitespace()).count();
+        let carets = "^".repeat(token_chars.max(1));
+        format!(
+            "{gutter} |\n{line_no} | {}\n{gutter} | {indent}{carets}",
+            self.line_text
+        )
+    }
 }
 
 impl core::error::Error for HkannoParseError {}
 impl core::fmt::Display for HkannoParseError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "Parse Error: {}", self.message)
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        match self.expected.as_slice() {
+            [] => write!(f, "invalid hkanno syntax")?,
+            [expected] => write!(f, "expected {expected}")?,
+            [init @ .., last] => write!(f, "expected {} or {last}", init.join(", "))?,
+        }
+        write!(f, "\n{}", self.snippet())
     }
 }
 
+/// Shorthand for a `StrContext::Expected` description.
+const fn expected(description: &'static str) -> StrContext {
+    StrContext::Expected(StrContextValue::Description(description))
+}
+
 /// Parses an entire hkanno text document into an [`Hkanno`] structure.
 ///
 /// This is the main entry point for parsing hkanno-formatted text.
 ///
 /// # Behavior
 ///
-/// - Skips all leading comment lines (`# ...`)
+/// - Reads the leading header comments (`# ptr: #NNNN`, `# hkanno v2`, `# numOriginalFrames: <i32>`,
+///   `# duration: <f32>`, `# numAnnotationTracks: <usize>`, `# fps: <f32>`); any other leading
+///   comment line (`# ...`) is skipped
 /// - Parses zero or more annotation tracks
+/// - Accepts annotation times in seconds (`0.4`) or frames (`f12`, `12f`), converting
+///   frames with `# fps` (default [`DEFAULT_FPS`](crate::DEFAULT_FPS))
 /// - Borrows string data directly from `input` where possible
 ///
 /// # Note
-/// Global animation fields such as frame count and duration are currently
-/// left at default values (`0` / `0.0`) and are expected to be filled
-/// elsewhere if needed.
+/// Header fields that are absent are left at their default values (`0` / `0.0` / `None`).
 ///
 /// # Lifetimes
 ///
@@ -51,28 +140,319 @@ impl core::fmt::Display for HkannoParseError {
 ///
 /// # Errors
 ///
-/// Returns [`HkannoParseError`] if the input does not conform to the expected
-/// hkanno text format.
+/// Returns [`HkannoParseError`] with the location of the first malformed line
+/// if the input does not conform to the expected hkanno text format, or of the
+/// second `# ptr:` header if the text holds several sections (parse those with
+/// [`parse_hkanno_document_str`]).
 pub fn parse_hkanno_str(input: &str) -> Result<Hkanno<'_>, HkannoParseError> {
-    match hkanno.parse(input) {
-        Ok(h) => Ok(h),
-        Err(e) => Err(HkannoParseError {
-            message: e.to_string(),
+    if let Some(offset) = second_ptr_offset(input) {
+        return Err(HkannoParseError::new(input, offset, vec![SINGLE_SECTION]));
+    }
+    hkanno
+        .parse(input)
+        .map_err(|e| HkannoParseError::new(input, e.offset(), expected_of(e.inner())))
+}
+
+/// Parses hkanno text, skipping malformed lines instead of stopping at the first one.
+///
+/// Every line that does not parse is reported as a diagnostic, and the returned
+/// [`Hkanno`] is built from the remaining valid lines. For well-formed input the
+/// result is the same as [`parse_hkanno_str`] and the diagnostics are empty.
+///
+/// # Recovery rules
+///
+/// - A malformed header comment (e.g. `# duration: abc`) is reported and its field keeps its default.
+/// - A malformed `trackName` line is reported; following annotations go to the previous track.
+/// - An annotation line before the first track, or any unrecognized line, is reported and dropped.
+/// - A second `# ptr:` header is reported and ignored, like in [`parse_hkanno_str`].
+pub fn parse_hkanno_str_recovering(input: &str) -> (Hkanno<'_>, Vec<HkannoParseError>) {
+    let mut header = Header::default();
+    let mut tracks: Vec<AnnotationTrack<'_>> = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen_ptr = false;
+
+    let mut line_start = 0;
+    for line in input.split_inclusive('\n') {
+        let line_offset = line_start;
+        line_start += line.len();
+
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        if parse_line(ptr_line, line).is_ok() {
+            if seen_ptr {
+                let offset = line_offset + line.len() - trimmed.len();
+                errors.push(HkannoParseError::new(input, offset, vec![SINGLE_SECTION]));
+                continue;
+            }
+            seen_ptr = true;
+        }
+
+        if trimmed.starts_with('#') {
+            // Comments above the first track can be header fields, those between a track
+            // name and its first annotation `# numAnnotations`; any other comment is free text.
+            let result = match tracks.last_mut() {
+                None => parse_line(header_field, line).map(|field| header.set(field)),
+                Some(track) if track.annotations.is_empty() => {
+                    parse_line(num_annotations_line, line).map(|n| track.num_annotations = Some(n))
+                }
+                Some(_) => Ok(()),
+            };
+            if let Err(Some((offset, expected))) = result {
+                errors.push(HkannoParseError::new(input, line_offset + offset, expected));
+            }
+            continue;
+        }
+
+        let failure = match parse_line(track_name_line, line) {
+            Ok(track_name) => {
+                tracks.push(AnnotationTrack {
+                    track_name,
+                    num_annotations: None,
+                    annotations: Vec::new(),
+                });
+                continue;
+            }
+            Err(Some(failure)) => failure,
+            Err(None) => match (
+                parse_line(annotation_line(header.fps.unwrap_or(DEFAULT_FPS)), line),
+                tracks.last_mut(),
+            ) {
+                (Ok(annotation), Some(track)) => {
+                    track.annotations.push(annotation);
+                    continue;
+                }
+                (Err(Some(failure)), _) => failure,
+                (Ok(_) | Err(None), None) => (line.len() - trimmed.len(), vec!["track name line"]),
+                (Err(None), Some(_)) => (
+                    line.len() - trimmed.len(),
+                    vec!["track name line", "float time"],
+                ),
+            },
+        };
+        let (offset, expected) = failure;
+        errors.push(HkannoParseError::new(input, line_offset + offset, expected));
+    }
+
+    let hkanno = Hkanno {
+        ptr: header.ptr.unwrap_or_default(),
+        num_original_frames: header.num_original_frames.unwrap_or_default(),
+        duration: header.duration.unwrap_or_default(),
+        num_annotation_tracks: header.num_annotation_tracks,
+        fps: header.fps,
+        annotation_tracks: tracks,
+    };
+    (hkanno, errors)
+}
+
+/// Parses a multi-animation hkanno document into an [`HkannoDocument`].
+///
+/// The document is split into sections at `# ptr: #NNNN` lines, and each section
+/// is parsed like [`parse_hkanno_str`], which reads [`Hkanno::ptr`] from that line.
+/// Only blank lines and comments may appear before the first `# ptr:` line.
+///
+/// Text without any `# ptr:` line is a single section with `ptr` `0`, i.e. a plain
+/// single-animation hkanno.
+///
+/// # Errors
+///
+/// Returns [`HkannoParseError`] with the location of the first malformed line.
+pub fn parse_hkanno_document_str(input: &str) -> Result<HkannoDocument<'_>, HkannoParseError> {
+    let (sections, mut errors) = split_sections(input);
+    if !errors.is_empty() {
+        return Err(errors.swap_remove(0));
+    }
+
+    let animations = sections
+        .into_iter()
+        .map(|section| parse_hkanno_str(section.text).map_err(|e| e.rebase(input, section.offset)))
+        .collect::<Result<_, _>>()?;
+    Ok(HkannoDocument { animations })
+}
+
+/// Parses a multi-animation hkanno document, skipping malformed lines.
+///
+/// This is the document counterpart of [`parse_hkanno_str_recovering`]; see
+/// [`parse_hkanno_document_str`] for the section rules.
+pub fn parse_hkanno_document_str_recovering(
+    input: &str,
+) -> (HkannoDocument<'_>, Vec<HkannoParseError>) {
+    let (sections, mut errors) = split_sections(input);
+
+    let animations = sections
+        .into_iter()
+        .map(|section| {
+            let (hkanno, section_errors) = parse_hkanno_str_recovering(section.text);
+            errors.extend(
+                section_errors
+                    .into_iter()
+                    .map(|e| e.rebase(input, section.offset)),
+            );
+            hkanno
+        })
+        .collect();
+    errors.sort_by_key(|e| e.span.start);
+
+    (HkannoDocument { animations }, errors)
+}
+
+/// One `# ptr: #NNNN` delimited part of a document.
+pub(crate) struct Section<'a> {
+    /// Byte offset of `text` within the whole document.
+    pub(crate) offset: usize,
+    /// Section text, starting with its `# ptr:` line (if any).
+    pub(crate) text: &'a str,
+}
+
+/// Splits a document at its `# ptr:` lines.
+///
+/// Lines before the first `# ptr:` line that are not blank or comments, and malformed
+/// `# ptr:` lines, are reported and left out of every section.
+pub(crate) fn split_sections(input: &str) -> (Vec<Section<'_>>, Vec<HkannoParseError>) {
+    let mut starts = Vec::new(); // line offsets
+    let mut preamble_lines = Vec::new(); // offsets of content lines before the first section
+    let mut errors = Vec::new();
+
+    let mut line_start = 0;
+    for line in input.split_inclusive('\n') {
+        let line_offset = line_start;
+        line_start += line.len();
+
+        match parse_line(ptr_line, line) {
+            Ok(_) => starts.push(line_offset),
+            Err(Some((offset, expected))) => {
+                errors.push(HkannoParseError::new(input, line_offset + offset, expected));
+            }
+            Err(None) => {
+                let trimmed = line.trim_start();
+                if starts.is_empty() && !trimmed.is_empty() && !trimmed.starts_with('#') {
+                    preamble_lines.push(line_offset + line.len() - trimmed.len());
+                }
+            }
+        }
+    }
+
+    if starts.is_empty() {
+        let section = Section {
+            offset: 0,
+            text: input,
+        };
+        return (vec![section], errors);
+    }
+
+    errors.extend(
+        preamble_lines.into_iter().map(|offset| {
+            HkannoParseError::new(input, offset, vec!["`# ptr: #NNNN` section header"])
         }),
+    );
+
+    let ends = starts.iter().skip(1).copied().chain([input.len()]);
+    let sections = starts
+        .iter()
+        .zip(ends)
+        .map(|(&offset, end)| Section {
+            offset,
+            text: &input[offset..end],
+        })
+        .collect();
+    (sections, errors)
+}
+
+/// What a single-section parser expects instead of a second `# ptr:` header.
+const SINGLE_SECTION: &str =
+    "a single section (use `parse_hkanno_document_str` for several `# ptr:` headers)";
+
+/// Byte offset of the `#` of the second `# ptr:` header line, if there is one.
+fn second_ptr_offset(input: &str) -> Option<usize> {
+    let mut line_start = 0;
+    let mut seen = false;
+    for line in input.split_inclusive('\n') {
+        let line_offset = line_start;
+        line_start += line.len();
+        if parse_line(ptr_line, line).is_ok() {
+            if seen {
+                return Some(line_offset + line.len() - line.trim_start().len());
+            }
+            seen = true;
+        }
     }
+    None
+}
+
+/// Parses a `# ptr: #0003` header line, which also starts a document section.
+fn ptr_line(input: &mut &str) -> ModalResult<usize> {
+    preceded((space0, "#", space0), ptr_field).parse_next(input)
+}
+
+/// Parses `ptr: #0003` (the leading `#` of the pointer is optional).
+fn ptr_field(input: &mut &str) -> ModalResult<usize> {
+    preceded(
+        (Caseless("ptr"), space0, ":", space0),
+        value(
+            preceded(opt("#"), digit1.try_map(str::parse)),
+            "pointer like `#0003`",
+        ),
+    )
+    .parse_next(input)
+}
+
+/// Runs a line parser over one line of input.
+///
+/// # Errors
+/// - `Some((offset, expected))` if the line was recognized but malformed (a cut error),
+///   with `offset` relative to the line start.
+/// - `None` if the line is simply not of this kind.
+pub(crate) fn parse_line<'a, O>(
+    mut parser: impl winnow::Parser<&'a str, O, ErrMode<ContextError>>,
+    line: &'a str,
+) -> Result<O, Option<(usize, Vec<&'static str>)>> {
+    let mut rest = line;
+    match parser.parse_next(&mut rest) {
+        Ok(output) => Ok(output),
+        Err(ErrMode::Cut(e)) => Err(Some((line.len() - rest.len(), expected_of(&e)))),
+        Err(_) => Err(None),
+    }
+}
+
+/// Collects the `Expected` descriptions attached to a winnow error.
+fn expected_of(error: &ContextError) -> Vec<&'static str> {
+    error
+        .context()
+        .filter_map(|context| match context {
+            StrContext::Expected(StrContextValue::Description(description)) => Some(*description),
+            _ => None,
+        })
+        .collect()
 }
 
 fn hkanno<'a>(input: &mut &'a str) -> ModalResult<Hkanno<'a>> {
-    ignore_blank_lines.parse_next(input)?;
+    let header = header.parse_next(input)?;
 
     let hkanno = Hkanno {
-        ptr: 0,
-        num_original_frames: 0,
-        duration: 0.0,
-        annotation_tracks: repeat(0.., track).parse_next(input)?,
+        ptr: header.ptr.unwrap_or_default(),
+        num_original_frames: header.num_original_frames.unwrap_or_default(),
+        duration: header.duration.unwrap_or_default(),
+        num_annotation_tracks: header.num_annotation_tracks,
+        fps: header.fps,
+        annotation_tracks: repeat(0.., track(header.fps.unwrap_or(DEFAULT_FPS)))
+            .parse_next(input)?,
     };
     ignore_blank_lines.parse_next(input)?;
-    winnow::ascii::multispace0.parse_next(input)?;
+    (winnow::ascii::multispace0, opt(("#", till_line_ending))).parse_next(input)?;
+
+    // Anything left over is a line that is neither a track name nor an annotation.
+    if hkanno.annotation_tracks.is_empty() {
+        cut_err(eof)
+            .context(expected("track name line"))
+            .parse_next(input)?;
+    } else {
+        cut_err(eof)
+            .context(expected("track name line"))
+            .context(expected("float time"))
+            .parse_next(input)?;
+    }
     Ok(hkanno)
 }
 
@@ -90,54 +470,208 @@ fn ignore_blank_line(input: &mut &str) -> ModalResult<()> {
     .parse_next(input)
 }
 
-/// Parses a single annotation track
-fn track<'a>(input: &mut &'a str) -> ModalResult<AnnotationTrack<'a>> {
-    winnow::seq! {
-        AnnotationTrack {
-            _: ignore_blank_lines,
-            track_name: track_name_line,
-            _: ignore_blank_lines,
-            annotations: repeat(0.., preceded(ignore_blank_lines, annotation_line)),
-            _: ignore_blank_lines,
+/// The only hkanno text format version this parser understands.
+const HKANNO_VERSION: u32 = 2;
+
+/// Global fields read from the leading `# key: value` comments.
+#[derive(Debug, Default)]
+struct Header {
+    ptr: Option<usize>,
+    num_original_frames: Option<i32>,
+    duration: Option<f32>,
+    num_annotation_tracks: Option<usize>,
+    fps: Option<f32>,
+}
+
+/// A single recognized header comment.
+pub(crate) enum HeaderField {
+    Version,
+    Ptr(usize),
+    NumOriginalFrames(i32),
+    Duration(f32),
+    NumAnnotationTracks(usize),
+    Fps(f32),
+}
+
+/// Parses the leading header comments and blank lines before the first track.
+///
+/// Unknown comments are skipped, so hand-written notes above the first track are still allowed.
+fn header(input: &mut &str) -> ModalResult<Header> {
+    repeat(
+        0..,
+        alt((header_field.map(Some), ignore_blank_line.map(|_| None))),
+    )
+    .fold(Header::default, |mut header, field| {
+        if let Some(field) = field {
+            header.set(field);
+        }
+        header
+    })
+    .parse_next(input)
+}
+
+impl Header {
+    fn set(&mut self, field: HeaderField) {
+        match field {
+            HeaderField::Version => {}
+            HeaderField::Ptr(ptr) => self.ptr = Some(ptr),
+            HeaderField::NumOriginalFrames(n) => self.num_original_frames = Some(n),
+            HeaderField::Duration(d) => self.duration = Some(d),
+            HeaderField::NumAnnotationTracks(n) => self.num_annotation_tracks = Some(n),
+            HeaderField::Fps(fps) => self.fps = Some(fps),
         }
     }
+}
+
+/// Parses one recognized header comment such as `# duration: 1.5`, `# ptr: #0003` or `# hkanno v2`.
+///
+/// Once `# <key>:` has matched, a malformed value is a hard error instead of
+/// silently being treated as a free comment.
+pub(crate) fn header_field(input: &mut &str) -> ModalResult<HeaderField> {
+    (space0, "#", space0).parse_next(input)?;
+    alt((
+        preceded(
+            (Caseless("hkanno"), space1, Caseless("v")),
+            value(
+                dec_uint::<_, u32, _>.verify(|version| *version == HKANNO_VERSION),
+                "hkanno version 2",
+            ),
+        )
+        .map(|_| HeaderField::Version),
+        ptr_field.map(HeaderField::Ptr),
+        preceded(
+            (Caseless("numOriginalFrames"), space0, ":", space0),
+            value(dec_int, "integer"),
+        )
+        .map(HeaderField::NumOriginalFrames),
+        preceded(
+            (Caseless("duration"), space0, ":", space0),
+            value(float, "float"),
+        )
+        .map(HeaderField::Duration),
+        preceded(
+            (Caseless("numAnnotationTracks"), space0, ":", space0),
+            value(dec_uint, "unsigned integer"),
+        )
+        .map(HeaderField::NumAnnotationTracks),
+        preceded(
+            (Caseless("fps"), space0, ":", space0),
+            value(
+                float.verify(|fps: &f32| is_valid_fps(*fps)),
+                "positive float",
+            ),
+        )
+        .map(HeaderField::Fps),
+    ))
+    .parse_next(input)
+}
+
+/// Parses the value of a recognized `# key:` comment up to the end of its line.
+///
+/// This is a cut point: a malformed value is reported rather than treated as a free comment.
+fn value<'a, O>(
+    parser: impl winnow::Parser<&'a str, O, ErrMode<ContextError>>,
+    description: &'static str,
+) -> impl winnow::Parser<&'a str, O, ErrMode<ContextError>> {
+    cut_err(terminated(parser, (space0, line_end))).context(expected(description))
+}
+
+/// Parses a `# numAnnotations: <usize>` comment.
+pub(crate) fn num_annotations_line(input: &mut &str) -> ModalResult<usize> {
+    preceded(
+        (
+            space0,
+            "#",
+            space0,
+            Caseless("numAnnotations"),
+            space0,
+            ":",
+            space0,
+        ),
+        value(dec_uint, "unsigned integer"),
+    )
     .parse_next(input)
 }
 
+/// Parses a single annotation track, reading frame times at `fps`
+fn track<'a>(fps: f32) -> impl FnMut(&mut &'a str) -> ModalResult<AnnotationTrack<'a>> {
+    move |input| {
+        winnow::seq! {
+            AnnotationTrack {
+                _: ignore_blank_lines,
+                track_name: track_name_line,
+                num_annotations: repeat(
+                    0..,
+                    alt((num_annotations_line.map(Some), ignore_blank_line.map(|_| None))),
+                )
+                .fold(|| None, |declared, n| n.or(declared)),
+                annotations: repeat(0.., preceded(ignore_blank_lines, annotation_line(fps))),
+                _: ignore_blank_lines,
+            }
+        }
+        .parse_next(input)
+    }
+}
+
 /// Parses a track name line like `trackName: Example Track`
-fn track_name_line<'a>(input: &mut &'a str) -> ModalResult<Option<Cow<'a, str>>> {
-    let (track_name,)=  winnow::seq! {
-        _: space0,
-        _: Caseless("trackName"),
+pub(crate) fn track_name_line<'a>(input: &mut &'a str) -> ModalResult<Option<Cow<'a, str>>> {
+    (space0, Caseless("trackName")).parse_next(input)?;
+
+    // Once `trackName` has matched, this line can only be a (possibly broken) track name line.
+    let (track_name,) = cut_err(winnow::seq! {
         _: space0,
-        _: ":",
+        _: ":".context(expected("`:` after trackName")),
         _: space0,
         till_line_ending.map(|name: &str| { if name == NULL_STR { None } else { Some(Cow::Borrowed(name)) } }
         ),
-        _: line_ending,
-    }
+        _: line_end,
+    })
     .parse_next(input)?;
 
     Ok(track_name)
 }
 
-/// Parses a single annotation line like `0.5555 annotation`
-fn annotation_line<'a>(input: &mut &'a str) -> ModalResult<Annotation<'a>> {
-    winnow::seq! {
-        Annotation {
-            _: space0,
-            time: float,
+/// Parses a single annotation line like `0.5555 annotation` or `f12 annotation`
+fn annotation_line<'a>(fps: f32) -> impl FnMut(&mut &'a str) -> ModalResult<Annotation<'a>> {
+    move |input| {
+        let time = preceded(space0, time(fps)).parse_next(input)?;
+
+        // Once the time has matched, the rest of the line must be its text.
+        let text = cut_err(winnow::seq! {
             _: space1,
-            text: till_line_ending.map(|name: &str| { if name == NULL_STR { None } else { Some(Cow::Borrowed(name)) } }),
-            _: line_ending,
-        }
+            till_line_ending.map(|name: &str| { if name == NULL_STR { None } else { Some(Cow::Borrowed(name)) } }),
+            _: line_end,
+        })
+        .context(expected("annotation text"))
+        .parse_next(input)?
+        .0;
+
+        Ok(Annotation { time, text })
     }
-    .parse_next(input)
+}
+
+/// Parses an annotation time in seconds (`0.4`) or frames (`f12` / `12f`) at `fps`.
+fn time(fps: f32) -> impl FnMut(&mut &str) -> ModalResult<f32> {
+    move |input| {
+        let frame = || digit1.try_map(str::parse::<u32>);
+        alt((
+            preceded("f", frame()).map(|frame| frame_time(frame, fps)),
+            terminated(frame(), "f").map(|frame| frame_time(frame, fps)),
+            float,
+        ))
+        .parse_next(input)
+    }
+}
+
+/// Line terminator for content lines; the last line of the input may omit it.
+fn line_end<'a>(input: &mut &'a str) -> ModalResult<&'a str> {
+    alt((line_ending, eof)).parse_next(input)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::TimeNotation;
 
     fn parse_ok(input: &str) -> Hkanno<'_> {
         parse_hkanno_str(input).unwrap_or_else(|e| panic!("parse should succeed: {e}"))
@@ -287,4 +821,287 @@ mod tests {
             "#,
         );
     }
+
+    #[test]
+    fn header_fields_are_parsed() {
+        let hkanno = parse_ok(
+            r#"
+            # ptr: #0003
+            # hkanno v2
+            # numOriginalFrames: 38
+            # duration: 1.5
+            # numAnnotationTracks: 1
+            # free comment
+
+            trackName: T
+            # numAnnotations: 1
+            0.1 a
+            "#,
+        );
+
+        assert_eq!(hkanno.ptr, 3);
+        assert_eq!(hkanno.num_original_frames, 38);
+        assert_eq!(hkanno.duration, 1.5);
+        assert_eq!(hkanno.num_annotation_tracks, Some(1));
+        assert_eq!(hkanno.annotation_tracks.len(), 1);
+        assert_eq!(hkanno.annotation_tracks[0].num_annotations, Some(1));
+    }
+
+    #[test]
+    fn missing_header_uses_defaults() {
+        let hkanno = parse_ok(
+            r#"
+            trackName: T
+            0.1 a
+            "#,
+        );
+
+        assert_eq!(hkanno.num_original_frames, 0);
+        assert_eq!(hkanno.duration, 0.0);
+        assert_eq!(hkanno.num_annotation_tracks, None);
+    }
+
+    #[test]
+    fn malformed_header_value_is_error() {
+        parse_err(
+            r#"
+            # duration: abc
+            trackName: T
+            "#,
+        );
+    }
+
+    #[test]
+    fn unsupported_version_is_error() {
+        parse_err(
+            r#"
+            # hkanno v3
+            trackName: T
+            "#,
+        );
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let hkanno = Hkanno {
+            ptr: 12,
+            num_original_frames: 46,
+            duration: 1.5333334,
+            num_annotation_tracks: Some(2),
+            fps: None,
+            annotation_tracks: vec![
+                AnnotationTrack {
+                    track_name: Some(Cow::Borrowed("PairedRoot")),
+                    num_annotations: Some(1),
+                    annotations: vec![Annotation {
+                        time: 0.1,
+                        text: Some(Cow::Borrowed("MCO_DodgeOpen")),
+                    }],
+                },
+                AnnotationTrack {
+                    track_name: None,
+                    num_annotations: Some(0),
+                    annotations: vec![],
+                },
+            ],
+        };
+
+        let text = hkanno.to_string();
+        assert_eq!(parse_ok(&text), hkanno);
+    }
+
+    #[test]
+    fn frame_times_use_fps_header() {
+        let hkanno = parse_ok("# fps: 60\ntrackName: T\nf12 a\n30f b\n0.5 c\n");
+        let times: Vec<_> = hkanno.annotation_tracks[0]
+            .annotations
+            .iter()
+            .map(|ann| ann.time)
+            .collect();
+        assert_eq!(hkanno.fps, Some(60.0));
+        assert_eq!(times, [0.2, 0.5, 0.5]);
+
+        let hkanno = parse_ok("trackName: T\nf15 a\n");
+        assert_eq!(hkanno.annotation_tracks[0].annotations[0].time, 0.5);
+    }
+
+    #[test]
+    fn frame_display_round_trip() {
+        let input = "# fps: 60\ntrackName: T\nf0 a\nf7 b\n0.123 between\nf89 c\n";
+        let hkanno = parse_ok(input);
+
+        let frames = hkanno.display(TimeNotation::Frames).to_string();
+        assert!(frames.contains("\nf7 b\n"));
+        assert!(frames.contains("\n0.123000 between\n"));
+        assert_eq!(
+            parse_ok(&frames).annotation_tracks[0].annotations,
+            hkanno.annotation_tracks[0].annotations
+        );
+
+        // Seconds are rounded to 6 decimals, but frame-snapped times stay on their frame.
+        let seconds = parse_ok(&hkanno.to_string())
+            .display(TimeNotation::Frames)
+            .to_string();
+        assert_eq!(seconds, frames);
+    }
+
+    #[test]
+    fn last_line_without_newline_is_ok() {
+        let hkanno = parse_ok("trackName: T\n0.5 hello");
+        assert_eq!(
+            hkanno.annotation_tracks[0].annotations[0].text.as_deref(),
+            Some("hello")
+        );
+    }
+
+    #[test]
+    fn error_points_at_offending_line() {
+        let err = parse_hkanno_str("trackName: T\n0.1 a\n  abc text\n").unwrap_err();
+
+        assert_eq!(err.line, 3);
+        assert_eq!(err.column, 3);
+        assert_eq!(err.span, 21..24);
+        assert_eq!(err.line_text, "  abc text");
+        assert_eq!(err.expected, ["track name line", "float time"]);
+        assert_eq!(err.snippet(), "  |\n3 |   abc text\n  |   ^^^");
+        assert!(err
+            .render("attack.hkx")
+            .starts_with("attack.hkx:3:3: expected track name line or float time\n"));
+    }
+
+    #[test]
+    fn snippet_counts_chars_and_keeps_tabs() {
+        let err = parse_hkanno_str("trackName: T\n\tüb text\n").unwrap_err();
+
+        assert_eq!((err.line, err.column, err.span.len()), (2, 2, 3));
+        assert_eq!(err.snippet(), "  |\n2 | \tüb text\n  | \t^^");
+    }
+
+    #[test]
+    fn error_for_missing_text_expects_annotation_text() {
+        let err = parse_hkanno_str("trackName: T\n0.5\n").unwrap_err();
+
+        assert_eq!((err.line, err.column), (2, 4));
+        assert_eq!(err.expected, ["annotation text"]);
+    }
+
+    #[test]
+    fn error_before_first_track_expects_track_name() {
+        let err = parse_hkanno_str("# hkanno v2\n0.0 orphan\n").unwrap_err();
+
+        assert_eq!((err.line, err.column), (2, 1));
+        assert_eq!(err.expected, ["track name line"]);
+    }
+
+    #[test]
+    fn recovering_reports_every_broken_line() {
+        let input = "# duration: abc\n0.0 orphan\ntrackName: T\n0.1 a\nabc text\n0.5\n0.9 b\n";
+        let (hkanno, errors) = parse_hkanno_str_recovering(input);
+
+        let lines: Vec<_> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [1, 2, 5, 6]);
+        assert_eq!(errors[0].expected, ["float"]);
+        assert_eq!(errors[1].expected, ["track name line"]);
+        assert_eq!(errors[2].expected, ["track name line", "float time"]);
+        assert_eq!(errors[3].expected, ["annotation text"]);
+
+        let track = &hkanno.annotation_tracks[0];
+        let texts: Vec<_> = track
+            .annotations
+            .iter()
+            .map(|a| a.text.as_deref())
+            .collect();
+        assert_eq!(texts, [Some("a"), Some("b")]);
+    }
+
+    #[test]
+    fn recovering_matches_strict_on_valid_input() {
+        let input = "# hkanno v2\n# duration: 1.5\n\ntrackName: A\n# numAnnotations: 1\n0.1 a\n\ntrackName: B\n0.2 b";
+        let (hkanno, errors) = parse_hkanno_str_recovering(input);
+
+        assert!(errors.is_empty());
+        assert_eq!(hkanno, parse_ok(input));
+    }
+
+    #[test]
+    fn recovering_treats_late_num_annotations_as_comment() {
+        for input in [
+            "trackName: T
+0.1 a
+# numAnnotations: 5
+",
+            "trackName: T
+0.1 a
+# numAnnotations: x
+0.2 b
+",
+        ] {
+            let (hkanno, errors) = parse_hkanno_str_recovering(input);
+
+            assert!(errors.is_empty());
+            assert_eq!(hkanno, parse_ok(input));
+            assert_eq!(hkanno.annotation_tracks[0].num_annotations, None);
+        }
+    }
+
+    #[test]
+    fn document_without_ptr_is_single_section() {
+        let doc = parse_hkanno_document_str("trackName: T\n0.1 a\n").unwrap();
+
+        assert_eq!(doc.animations.len(), 1);
+        assert_eq!(doc.animations[0].ptr, 0);
+    }
+
+    #[test]
+    fn document_sections_are_keyed_by_ptr() {
+        let doc = parse_hkanno_document_str(
+            "# comment\n# ptr: #0003\ntrackName: A\n0.1 a\n\n# ptr: #0010\ntrackName: B\n0.2 b\n",
+        )
+        .unwrap();
+
+        let ptrs: Vec<_> = doc.animations.iter().map(|h| h.ptr).collect();
+        assert_eq!(ptrs, [3, 10]);
+        assert_eq!(
+            doc.animations[1].annotation_tracks[0].track_name.as_deref(),
+            Some("B")
+        );
+    }
+
+    #[test]
+    fn document_error_lines_are_absolute() {
+        let input = "# ptr: #0003\ntrackName: A\n\n# ptr: #0010\ntrackName: B\nabc\n";
+        let err = parse_hkanno_document_str(input).unwrap_err();
+        assert_eq!((err.line, err.column), (6, 1));
+
+        let (doc, errors) = parse_hkanno_document_str_recovering(input);
+        assert_eq!(doc.animations.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 6);
+    }
+
+    #[test]
+    fn single_section_parsers_reject_a_second_ptr() {
+        let input = "# ptr: #0003\ntrackName: A\n0.1 a\n\n# ptr: #0007\ntrackName: B\n0.2 b\n";
+        let err = parse_hkanno_str(input).unwrap_err();
+        assert_eq!((err.line, err.column), (5, 1));
+        assert_eq!(err.expected, [SINGLE_SECTION]);
+
+        let (hkanno, errors) = parse_hkanno_str_recovering(input);
+        assert_eq!(hkanno.ptr, 3);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 5);
+
+        assert_eq!(
+            parse_hkanno_document_str(input).unwrap().animations.len(),
+            2
+        );
+    }
+
+    #[test]
+    fn document_rejects_tracks_before_first_ptr() {
+        let err =
+            parse_hkanno_document_str("trackName: A\n# ptr: #0003\ntrackName: B\n").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert_eq!(err.expected, ["`# ptr: #NNNN` section header"]);
+    }
 }
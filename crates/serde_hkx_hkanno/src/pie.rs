@@ -0,0 +1,354 @@
+//! PIE (payload instruction) schema.
+//!
+//! PIE native instructions are written as `PIE.@<NAME>|<param>|...`. An
+//! [`InstructionRegistry`] knows the parameters of each instruction so that
+//! payload annotations can be checked before they reach the game.
+//!
+//! The built-in instructions live in `pie_instructions.toml`; mods that add
+//! their own instructions can describe them in a TOML or JSON file of the same
+//! shape and load it with [`InstructionRegistry::extend_from_file`]:
+//!
+//! ```toml
+//! [[instruction]]
+//! name = "MYINSTR"
+//! documentation = "What it does."
+//! params = [{ name = "spell", kind = "formId" }, { name = "enable", kind = "bool" }]
+//! ```
+use std::{
+    collections::HashMap,
+    fmt,
+    path::Path,
+    sync::{Arc, LazyLock},
+};
+
+use snafu::ResultExt as _;
+
+use crate::{HkannoError, IoSnafu};
+
+/// Type of one instruction parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ParamKind {
+    /// Form ID spanning two fields: a hex ID and its plugin, e.g. `0x01|MyMod.esp`.
+    FormId,
+    /// `true`/`false` or `1`/`0`.
+    Bool,
+    /// Integer.
+    Int,
+    /// Floating point number.
+    Float,
+    /// Behavior graph variable name, e.g. `bIsAttacking`.
+    GraphVariable,
+    /// Any text.
+    String,
+}
+
+impl ParamKind {
+    /// Number of `|`-separated fields this parameter spans.
+    pub const fn width(self) -> usize {
+        match self {
+            Self::FormId => 2,
+            _ => 1,
+        }
+    }
+
+    /// Does `fields` (exactly [`Self::width`] fields) hold a valid value?
+    fn accepts(self, fields: &[&str]) -> bool {
+        match (self, fields) {
+            (Self::FormId, [id, plugin]) => {
+                let hex = id
+                    .strip_prefix("0x")
+                    .or_else(|| id.strip_prefix("0X"))
+                    .unwrap_or(id);
+                let plugin = plugin.to_ascii_lowercase();
+                !hex.is_empty()
+                    && u32::from_str_radix(hex, 16).is_ok()
+                    && [".esp", ".esm", ".esl"]
+                        .iter()
+                        .any(|ext| plugin.len() > ext.len() && plugin.ends_with(ext))
+            }
+            (Self::Bool, [value]) => ["true", "false", "1", "0"]
+                .iter()
+                .any(|b| value.eq_ignore_ascii_case(b)),
+            (Self::Int, [value]) => value.parse::<i64>().is_ok(),
+            (Self::Float, [value]) => value.parse::<f32>().is_ok_and(f32::is_finite),
+            (Self::GraphVariable, [value]) => {
+                !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            }
+            (Self::String, [_]) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ParamKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::FormId => "form ID (`0x<hex>|<plugin>`)",
+            Self::Bool => "bool",
+            Self::Int => "integer",
+            Self::Float => "float",
+            Self::GraphVariable => "graph variable name",
+            Self::String => "string",
+        })
+    }
+}
+
+/// One named instruction parameter.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ParamDef {
+    /// Name shown in diagnostics.
+    pub name: String,
+    /// Expected type.
+    pub kind: ParamKind,
+}
+
+/// Definition of one PIE native instruction.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct InstructionDef {
+    /// Instruction name, matched case-insensitively, e.g. `CASTSPELL`.
+    pub name: String,
+    /// Human readable description.
+    #[serde(default)]
+    pub documentation: String,
+    /// Parameters in order.
+    #[serde(default)]
+    pub params: Vec<ParamDef>,
+}
+
+impl InstructionDef {
+    /// Number of `|`-separated fields the instruction takes.
+    pub fn arity(&self) -> usize {
+        self.params.iter().map(|param| param.kind.width()).sum()
+    }
+
+    /// Checks the `|`-separated `fields` of a payload against this definition.
+    ///
+    /// # Errors
+    /// On the wrong number of fields, or on the first field of the wrong type.
+    pub fn check<S: AsRef<str>>(&self, fields: &[S]) -> Result<(), PayloadError> {
+        let fields: Vec<&str> = fields.iter().map(AsRef::as_ref).collect();
+        if fields.len() != self.arity() {
+            return Err(PayloadError::ArityMismatch {
+                name: self.name.clone(),
+                expected: self.arity(),
+                actual: fields.len(),
+            });
+        }
+
+        let mut rest = fields.as_slice();
+        for param in &self.params {
+            let (value, tail) = rest.split_at(param.kind.width());
+            if !param.kind.accepts(value) {
+                return Err(PayloadError::InvalidArgument {
+                    param: param.name.clone(),
+                    expected: param.kind,
+                    value: value.join("|"),
+                });
+            }
+            rest = tail;
+        }
+        Ok(())
+    }
+}
+
+/// Why a payload does not match its [`InstructionDef`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum PayloadError {
+    /// Wrong number of `|`-separated fields.
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// A field does not hold a value of its parameter's type.
+    InvalidArgument {
+        param: String,
+        expected: ParamKind,
+        value: String,
+    },
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ArityMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(f, "`{name}` takes {expected} field(s) but got {actual}"),
+            Self::InvalidArgument {
+                param,
+                expected,
+                value,
+            } => {
+                write!(f, "`{param}` expects a {expected}, but got `{value}`")
+            }
+        }
+    }
+}
+
+/// File layout of instruction definitions (`pie_instructions.toml`).
+#[derive(Debug, Default, serde::Deserialize)]
+struct InstructionFile {
+    #[serde(default)]
+    instruction: Vec<InstructionDef>,
+}
+
+/// Built-in definitions, parsed once.
+static BUILTIN: LazyLock<Arc<InstructionRegistry>> = LazyLock::new(|| {
+    let mut registry = InstructionRegistry::default();
+    registry
+        .extend_from_toml(include_str!("pie_instructions.toml"))
+        .expect("built-in PIE instructions must be valid TOML");
+    Arc::new(registry)
+});
+
+/// Set of known PIE native instructions, keyed by case-insensitive name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstructionRegistry {
+    instructions: HashMap<String, InstructionDef>,
+}
+
+impl InstructionRegistry {
+    /// Returns the shared built-in registry (SGVB, SGVF, SGVI, CASTSPELL, APPLYSPELL,
+    /// UNAPPLYSPELL, SETGHOST, PLAYPARTICLE).
+    pub fn builtin() -> Arc<Self> {
+        Arc::clone(&BUILTIN)
+    }
+
+    /// Adds `def`, replacing any instruction of the same name.
+    pub fn insert(&mut self, def: InstructionDef) {
+        self.instructions.insert(def.name.to_ascii_uppercase(), def);
+    }
+
+    /// Looks an instruction up by case-insensitive name.
+    pub fn get(&self, name: &str) -> Option<&InstructionDef> {
+        self.instructions.get(&name.to_ascii_uppercase())
+    }
+
+    /// Iterates over every instruction in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &InstructionDef> {
+        self.instructions.values()
+    }
+
+    /// Adds the instructions of a TOML document.
+    ///
+    /// # Errors
+    /// If `text` is not a valid instruction file.
+    pub fn extend_from_toml(&mut self, text: &str) -> Result<(), HkannoError> {
+        let file: InstructionFile =
+            toml::from_str(text).map_err(|e| HkannoError::InvalidInstructionFile {
+                message: e.to_string(),
+            })?;
+        file.instruction
+            .into_iter()
+            .for_each(|def| self.insert(def));
+        Ok(())
+    }
+
+    /// Adds the instructions of a JSON document (`{ "instruction": [...] }`).
+    ///
+    /// # Errors
+    /// If `text` is not a valid instruction file.
+    pub fn extend_from_json(&mut self, text: &str) -> Result<(), HkannoError> {
+        let file: InstructionFile =
+            serde_json::from_str(text).map_err(|e| HkannoError::InvalidInstructionFile {
+                message: e.to_string(),
+            })?;
+        file.instruction
+            .into_iter()
+            .for_each(|def| self.insert(def));
+        Ok(())
+    }
+
+    /// Adds the instructions of a `.toml` or `.json` file.
+    ///
+    /// # Errors
+    /// If the file cannot be read, has another extension, or is not a valid instruction file.
+    pub fn extend_from_file(&mut self, path: &Path) -> Result<(), HkannoError> {
+        let text = std::fs::read_to_string(path).with_context(|_| IoSnafu { path })?;
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+
+        let result = match extension.as_deref() {
+            Some("toml") => self.extend_from_toml(&text),
+            Some("json") => self.extend_from_json(&text),
+            _ => Err(HkannoError::InvalidInstructionFile {
+                message: "expected a `.toml` or `.json` file".to_string(),
+            }),
+        };
+        result.map_err(|e| HkannoError::HkxError {
+            source: Box::new(e),
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_instructions_check_arguments() {
+        let registry = InstructionRegistry::builtin();
+        let check = |name: &str, fields: &[&str]| registry.get(name).unwrap().check(fields);
+
+        assert_eq!(check("sgvf", &["fSpeed", "1.5"]), Ok(()));
+        assert_eq!(check("APPLYSPELL", &["0x01", "MyMod.esp"]), Ok(()));
+        assert_eq!(
+            check(
+                "PLAYPARTICLE",
+                &["a.nif", "0", "1", "2", "0", "0", "0", "0"]
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check("SETGHOST", &["yes"]),
+            Err(PayloadError::InvalidArgument {
+                param: "value".to_string(),
+                expected: ParamKind::Bool,
+                value: "yes".to_string(),
+            })
+        );
+        assert_eq!(
+            check("APPLYSPELL", &["0xZZ", "MyMod.esp"]).map_err(|e| e.to_string()),
+            Err(
+                "`spell` expects a form ID (`0x<hex>|<plugin>`), but got `0xZZ|MyMod.esp`"
+                    .to_string()
+            )
+        );
+        assert!(matches!(
+            check("SGVB", &["bFlag"]),
+            Err(PayloadError::ArityMismatch {
+                expected: 2,
+                actual: 1,
+                ..
+            })
+        ));
+        assert!(registry.get("UNKNOWN").is_none());
+    }
+
+    #[test]
+    fn user_definitions_extend_and_override() {
+        let mut registry = (*InstructionRegistry::builtin()).clone();
+        registry
+            .extend_from_json(
+                r#"{ "instruction": [
+                    { "name": "MyInstr", "params": [{ "name": "n", "kind": "int" }] },
+                    { "name": "SETGHOST", "params": [] }
+                ] }"#,
+            )
+            .unwrap();
+
+        assert_eq!(registry.get("MYINSTR").unwrap().check(&["3"]), Ok(()));
+        assert_eq!(registry.get("SETGHOST").unwrap().arity(), 0);
+        assert!(matches!(
+            registry.extend_from_toml("[[instruction]]\nname = 1"),
+            Err(HkannoError::InvalidInstructionFile { .. })
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+//! Machine-readable results of dumping, validating, applying, exporting and retiming annotations.
+//!
+//! Every file processed produces one [`Record`], written as JSON either one per
+//! line (JSON Lines, see [`to_json_line`]) or all together (see [`to_json`]).
+//! Both carry [`SCHEMA_VERSION`]:
+//!
+//! ```txt
+//! {"schemaVersion":1,"kind":"dump","path":"idle.hkx","annotationPath":"idle.txt","written":true,"document":{...}}
+//! {"schemaVersion":1,"kind":"apply","path":"idle.hkx","output":"idle.hkx","outcome":"unchanged","inputFormat":"amd64"}
+//!
+//! {"schemaVersion":1,"records":[{"kind":"dump",...},{"kind":"error","path":null,"message":"..."}]}
+//! ```
+//!
+//! `document` is an [`HkannoDocument`] with its fields as they are in Rust
+//! (`annotation_tracks`, `track_name`, ...), so it can be deserialized back.
+//!
+//! The version is bumped whenever a field is removed, renamed or changes meaning;
+//! new record kinds and fields may be added without a bump.
+use std::path::PathBuf;
+
+use crate::{
+    editor::ApplyOutcome, HkannoDiff, HkannoDocument, HkannoParseError, HkxFormat, Severity,
+    ValidationIssue,
+};
+
+/// Version of the records' JSON schema.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The result of one file.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Record<'a> {
+    /// Annotations extracted from `path`.
+    Dump {
+        path: PathBuf,
+        /// Where the hkanno text of the file belongs.
+        annotation_path: PathBuf,
+        /// Whether the hkanno text was written to `annotation_path`.
+        written: bool,
+        document: HkannoDocument<'a>,
+    },
+    /// Annotations applied to `path`.
+    Apply {
+        path: PathBuf,
+        output: PathBuf,
+        outcome: ApplyOutcome,
+        /// Format detected from the input's header (`null` if not recognized).
+        input_format: Option<HkxFormat>,
+    },
+    /// Annotations of `path` written to an annotation table.
+    Export {
+        path: PathBuf,
+        table: PathBuf,
+        /// Number of annotations (rows) exported.
+        rows: usize,
+    },
+    /// Annotation times of `path` changed in its hkanno text.
+    Retime {
+        path: PathBuf,
+        annotation_path: PathBuf,
+        /// Number of annotations whose time changed; the text is only written if any did.
+        changed: usize,
+    },
+    /// What applying or retiming annotations of `path` would change (dry run).
+    Diff { path: PathBuf, diff: HkannoDiff },
+    /// XML of `path` with its annotations applied.
+    Preview {
+        path: PathBuf,
+        /// File the XML was written to, if any.
+        output: Option<PathBuf>,
+        /// The XML itself, unless it was written to `output`.
+        xml: Option<String>,
+    },
+    /// Problems found in the annotations of `path`.
+    Validation {
+        path: PathBuf,
+        annotation_path: PathBuf,
+        /// Syntax errors; the annotations are not checked further if there are any.
+        syntax_errors: Vec<HkannoParseError>,
+        findings: Vec<Finding>,
+    },
+    /// `path` was not processed, e.g. because it has no hkanno text.
+    Skipped { path: PathBuf, reason: String },
+    /// Processing failed; `path` is `null` if the failure is not tied to one file.
+    Error {
+        path: Option<PathBuf>,
+        message: String,
+    },
+}
+
+/// A [`ValidationIssue`] of one animation.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Finding {
+    /// Pointer of the animation section (`0` if the section has no `# ptr:`).
+    pub ptr: usize,
+    pub severity: Severity,
+    /// Human-readable description.
+    pub message: String,
+    pub issue: ValidationIssue,
+}
+
+impl Finding {
+    /// Describes `issue` of the section at `ptr`.
+    pub fn new(ptr: usize, issue: ValidationIssue) -> Self {
+        Self {
+            ptr,
+            severity: issue.severity(),
+            message: issue.to_string(),
+            issue,
+        }
+    }
+}
+
+/// `value` with the schema version added in front of its own fields.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Versioned<T> {
+    schema_version: u32,
+    #[serde(flatten)]
+    value: T,
+}
+
+/// Records serialized all at once.
+#[derive(serde::Serialize)]
+struct Records<'r, 'a> {
+    records: &'r [Record<'a>],
+}
+
+/// Serializes `record` as one line of JSON Lines (without the line break).
+///
+/// # Errors
+/// If serialization fails.
+pub fn to_json_line(record: &Record<'_>) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&Versioned {
+        schema_version: SCHEMA_VERSION,
+        value: record,
+    })
+}
+
+/// Serializes `records` as a single pretty-printed JSON object.
+///
+/// # Errors
+/// If serialization fails.
+pub fn to_json(records: &[Record<'_>]) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(&Versioned {
+        schema_version: SCHEMA_VERSION,
+        value: Records { records },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Annotation, AnnotationTrack, Hkanno};
+    use std::borrow::Cow;
+
+    #[test]
+    fn records_carry_schema_version_and_kind() {
+        let document = HkannoDocument::from(Hkanno {
+            ptr: 3,
+            num_original_frames: 38,
+            duration: 1.5,
+            num_annotation_tracks: None,
+            fps: None,
+            annotation_tracks: vec![AnnotationTrack {
+                track_name: Some(Cow::Borrowed("PairedRoot")),
+                num_annotations: None,
+                annotations: vec![Annotation {
+                    time: 0.5,
+                    text: Some(Cow::Borrowed("SoundPlay.NPCHumanCombatShieldBash")),
+                }],
+            }],
+        });
+        let dump = Record::Dump {
+            path: PathBuf::from("idle.hkx"),
+            annotation_path: PathBuf::from("idle.txt"),
+            written: true,
+            document: document.clone(),
+        };
+
+        let line: serde_json::Value = serde_json::from_str(&to_json_line(&dump).unwrap()).unwrap();
+        assert_eq!(line["schemaVersion"], SCHEMA_VERSION);
+        assert_eq!(line["kind"], "dump");
+        assert_eq!(line["annotationPath"], "idle.txt");
+        let round_trip: HkannoDocument<'_> =
+            serde_json::from_value(line["document"].clone()).unwrap();
+        assert_eq!(round_trip, document);
+
+        let validation = Record::Validation {
+            path: PathBuf::from("idle.hkx"),
+            annotation_path: PathBuf::from("idle.txt"),
+            syntax_errors: Vec::new(),
+            findings: vec![Finding::new(
+                3,
+                ValidationIssue::EmptyText { track: 0, index: 1 },
+            )],
+        };
+        let error = Record::Error {
+            path: None,
+            message: "Path does not exist".to_string(),
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json(&[validation, error]).unwrap()).unwrap();
+        assert_eq!(json["schemaVersion"], SCHEMA_VERSION);
+        let finding = &json["records"][0]["findings"][0];
+        assert_eq!(finding["severity"], "warning");
+        assert_eq!(finding["issue"]["kind"], "emptyText");
+        assert_eq!(json["records"][1]["kind"], "error");
+        assert!(json["records"][1]["path"].is_null());
+    }
+}
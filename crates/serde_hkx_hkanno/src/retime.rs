@@ -0,0 +1,347 @@
+//! Batch changes of annotation times.
+//!
+//! When an animation is retimed in the DCC tool, its annotations keep their old
+//! times. [`Retime`] moves them all at once, optionally only those selected by an
+//! [`AnnotationFilter`]:
+//!
+//! ```txt
+//! # duration: 2.0              (was 1.5)
+//! trackName: PairedRoot
+//! 0.400000 MCO_DodgeClose      <- `Retime::Remap { from_duration: 1.5 }` writes 0.533333
+//! ```
+//!
+//! Annotations keep their order within a track; if a filtered change moves one past
+//! its neighbours, validation reports it as out of order.
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use havok_types::NULL_STR;
+use snafu::ResultExt as _;
+
+use crate::{
+    frame_time, Annotation, Hkanno, HkannoDocument, HkannoError, InvalidEventPatternSnafu,
+};
+
+/// How the selected annotation times change.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(
+    tag = "kind",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum Retime {
+    /// Multiply every time by `factor`.
+    Scale { factor: f32 },
+    /// Map times of an animation that lasted `from_duration` seconds onto the
+    /// section's `duration`.
+    Remap { from_duration: f32 },
+    /// Add `offset` seconds (negative: earlier), clamped to `0..=duration`.
+    ///
+    /// Without a declared duration only the lower bound applies.
+    Shift { offset: f32 },
+    /// Round every time to the nearest frame at `fps`.
+    Snap { fps: f32 },
+}
+
+/// Which annotations a [`Retime`] applies to.
+///
+/// The default filter selects everything.
+#[derive(Debug, Clone, Default)]
+pub struct AnnotationFilter {
+    /// Track names to include (ASCII case-insensitive); empty: every track.
+    tracks: Vec<String>,
+    /// Glob patterns matched case-insensitively against the whole annotation text,
+    /// e.g. `SoundPlay.*` or `PIE.@CASTSPELL*`; `None`: every annotation.
+    events: Option<GlobSet>,
+}
+
+impl AnnotationFilter {
+    /// Selects annotations of the named `tracks` whose text matches any of `events`.
+    ///
+    /// An empty list does not restrict that criterion.
+    ///
+    /// # Errors
+    /// If a pattern is not a valid glob.
+    pub fn new(tracks: Vec<String>, events: &[String]) -> Result<Self, HkannoError> {
+        let events = match events.is_empty() {
+            true => None,
+            false => {
+                let mut builder = GlobSetBuilder::new();
+                for pattern in events {
+                    let glob = GlobBuilder::new(pattern)
+                        .case_insensitive(true)
+                        .build()
+                        .context(InvalidEventPatternSnafu { pattern })?;
+                    builder.add(glob);
+                }
+                Some(builder.build().context(InvalidEventPatternSnafu {
+                    pattern: events.join(", "),
+                })?)
+            }
+        };
+        Ok(Self { tracks, events })
+    }
+
+    /// Whether the track named `track_name` is selected.
+    pub fn matches_track(&self, track_name: Option<&str>) -> bool {
+        let name = track_name.unwrap_or(NULL_STR);
+        self.tracks.is_empty() || self.tracks.iter().any(|t| t.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether `annotation` is selected, given that its track is.
+    pub fn matches_annotation(&self, annotation: &Annotation<'_>) -> bool {
+        self.events
+            .as_ref()
+            .is_none_or(|events| events.is_match(annotation.text.as_deref().unwrap_or(NULL_STR)))
+    }
+}
+
+impl Retime {
+    /// Rejects arguments that would produce meaningless times.
+    fn check(self, duration: f32) -> Result<(), HkannoError> {
+        let reason = match self {
+            Self::Scale { factor } if !(factor.is_finite() && factor >= 0.0) => {
+                "the scale factor must be a non-negative number"
+            }
+            Self::Remap { from_duration }
+                if !(from_duration.is_finite() && from_duration > 0.0) =>
+            {
+                "the old duration must be positive"
+            }
+            Self::Remap { .. } if duration <= 0.0 => "the section declares no duration to map onto",
+            Self::Shift { offset } if !offset.is_finite() => "the offset must be a number",
+            Self::Snap { fps } if !(fps.is_finite() && fps > 0.0) => {
+                "the frame rate must be positive"
+            }
+            _ => return Ok(()),
+        };
+        Err(HkannoError::InvalidRetime {
+            reason: reason.to_string(),
+        })
+    }
+
+    /// The new value of `time` in a section lasting `duration` seconds.
+    fn apply(self, time: f32, duration: f32) -> f32 {
+        match self {
+            Self::Scale { factor } => time * factor,
+            Self::Remap { from_duration } => time * duration / from_duration,
+            Self::Shift { offset } => {
+                let time = (time + offset).max(0.0);
+                match duration > 0.0 {
+                    true => time.min(duration),
+                    false => time,
+                }
+            }
+            Self::Snap { fps } => frame_time((time * fps).round().max(0.0) as u32, fps),
+        }
+    }
+}
+
+impl Hkanno<'_> {
+    /// Changes the times of the annotations selected by `filter`.
+    ///
+    /// Returns how many times changed.
+    ///
+    /// # Errors
+    /// [`HkannoError::InvalidRetime`] if the factor, duration or frame rate is not
+    /// positive and finite (the factor may be `0`), or if [`Retime::Remap`] is used on
+    /// a section without a declared duration. Nothing is changed then.
+    pub fn retime(
+        &mut self,
+        retime: Retime,
+        filter: &AnnotationFilter,
+    ) -> Result<usize, HkannoError> {
+        retime.check(self.duration)?;
+
+        let mut changed = 0;
+        for track in &mut self.annotation_tracks {
+            if !filter.matches_track(track.track_name.as_deref()) {
+                continue;
+            }
+            for ann in &mut track.annotations {
+                if !filter.matches_annotation(ann) {
+                    continue;
+                }
+                let time = retime.apply(ann.time, self.duration);
+                if time.to_bits() != ann.time.to_bits() {
+                    ann.time = time;
+                    changed += 1;
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Multiplies the selected times by `factor`. See [`Self::retime`].
+    ///
+    /// # Errors
+    /// If `factor` is negative or not finite.
+    pub fn scale_times(
+        &mut self,
+        factor: f32,
+        filter: &AnnotationFilter,
+    ) -> Result<usize, HkannoError> {
+        self.retime(Retime::Scale { factor }, filter)
+    }
+
+    /// Maps the selected times from an animation of `from_duration` seconds onto
+    /// [`Self::duration`]. See [`Self::retime`].
+    ///
+    /// # Errors
+    /// If either duration is not positive.
+    pub fn remap_times(
+        &mut self,
+        from_duration: f32,
+        filter: &AnnotationFilter,
+    ) -> Result<usize, HkannoError> {
+        self.retime(Retime::Remap { from_duration }, filter)
+    }
+
+    /// Adds `offset` seconds to the selected times, clamped to `0..=duration`. See [`Self::retime`].
+    ///
+    /// # Errors
+    /// If `offset` is not finite.
+    pub fn shift_times(
+        &mut self,
+        offset: f32,
+        filter: &AnnotationFilter,
+    ) -> Result<usize, HkannoError> {
+        self.retime(Retime::Shift { offset }, filter)
+    }
+
+    /// Rounds the selected times to the nearest frame at `fps`. See [`Self::retime`].
+    ///
+    /// # Errors
+    /// If `fps` is not positive.
+    pub fn snap_times(
+        &mut self,
+        fps: f32,
+        filter: &AnnotationFilter,
+    ) -> Result<usize, HkannoError> {
+        self.retime(Retime::Snap { fps }, filter)
+    }
+}
+
+impl HkannoDocument<'_> {
+    /// [`Hkanno::retime`] on every section.
+    ///
+    /// Returns how many times changed in total.
+    ///
+    /// # Errors
+    /// Same as [`Hkanno::retime`]. Every section is checked first, so on error
+    /// no section has changed.
+    pub fn retime(
+        &mut self,
+        retime: Retime,
+        filter: &AnnotationFilter,
+    ) -> Result<usize, HkannoError> {
+        for hkanno in &self.animations {
+            retime.check(hkanno.duration)?;
+        }
+        self.animations.iter_mut().try_fold(0, |changed, hkanno| {
+            Ok(changed + hkanno.retime(retime, filter)?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_hkanno_document_str, parse_hkanno_str};
+
+    const TEXT: &str = "\
+# duration: 2.0
+trackName: PairedRoot
+0.1 SoundPlay.WPNSwingUnarmed
+0.4 MCO_DodgeClose
+1.5 SoundPlay.NPCHumanCombatShieldBash
+
+trackName: Foot_L
+0.25 FootLeft
+";
+
+    /// Asserts the times of every track, allowing for `f32` error.
+    #[track_caller]
+    fn assert_times(hkanno: &Hkanno<'_>, expected: &[&[f32]]) {
+        let times: Vec<Vec<f32>> = hkanno
+            .annotation_tracks
+            .iter()
+            .map(|track| track.annotations.iter().map(|ann| ann.time).collect())
+            .collect();
+        let close = times.len() == expected.len()
+            && times.iter().zip(expected).all(|(times, expected)| {
+                times.len() == expected.len()
+                    && times
+                        .iter()
+                        .zip(*expected)
+                        .all(|(a, b)| (a - b).abs() <= 1e-6)
+            });
+        assert!(close, "{times:?} != {expected:?}");
+    }
+
+    #[test]
+    fn scale_remap_shift_and_snap() {
+        let all = AnnotationFilter::default();
+
+        let mut hkanno = parse_hkanno_str(TEXT).unwrap();
+        assert_eq!(hkanno.scale_times(2.0, &all).unwrap(), 4);
+        assert_times(&hkanno, &[&[0.2, 0.8, 3.0], &[0.5]]);
+
+        let mut hkanno = parse_hkanno_str(TEXT).unwrap();
+        hkanno.remap_times(1.0, &all).unwrap();
+        assert_times(&hkanno, &[&[0.2, 0.8, 3.0], &[0.5]]);
+
+        let mut hkanno = parse_hkanno_str(TEXT).unwrap();
+        hkanno.shift_times(0.75, &all).unwrap();
+        assert_times(&hkanno, &[&[0.85, 1.15, 2.0], &[1.0]]);
+        hkanno.shift_times(-1.0, &all).unwrap();
+        assert_times(&hkanno, &[&[0.0, 0.15, 1.0], &[0.0]]);
+
+        let mut hkanno = parse_hkanno_str(TEXT).unwrap();
+        assert_eq!(hkanno.snap_times(10.0, &all).unwrap(), 1);
+        assert_times(&hkanno, &[&[0.1, 0.4, 1.5], &[0.3]]);
+    }
+
+    #[test]
+    fn filter_selects_tracks_and_events() {
+        let mut hkanno = parse_hkanno_str(TEXT).unwrap();
+        let sounds =
+            AnnotationFilter::new(vec!["pairedroot".into()], &["soundplay.*".into()]).unwrap();
+        assert_eq!(hkanno.shift_times(0.1, &sounds).unwrap(), 2);
+        assert_times(&hkanno, &[&[0.2, 0.4, 1.6], &[0.25]]);
+
+        let feet = AnnotationFilter::new(vec!["Foot_L".into()], &[]).unwrap();
+        assert_eq!(hkanno.scale_times(2.0, &feet).unwrap(), 1);
+        assert_times(&hkanno, &[&[0.2, 0.4, 1.6], &[0.5]]);
+
+        assert!(AnnotationFilter::new(Vec::new(), &["[".into()]).is_err());
+    }
+
+    #[test]
+    fn invalid_retimes_change_nothing() {
+        let all = AnnotationFilter::default();
+        let mut hkanno = parse_hkanno_str(TEXT).unwrap();
+        assert!(hkanno.scale_times(-1.0, &all).is_err());
+        assert!(hkanno.snap_times(0.0, &all).is_err());
+        assert!(hkanno.remap_times(f32::NAN, &all).is_err());
+
+        hkanno.duration = 0.0;
+        assert!(hkanno.remap_times(1.0, &all).is_err());
+        assert_eq!(hkanno, {
+            let mut original = parse_hkanno_str(TEXT).unwrap();
+            original.duration = 0.0;
+            original
+        });
+    }
+
+    #[test]
+    fn document_retime_is_all_or_nothing() {
+        let text = "# ptr: #0001\n# duration: 1.0\ntrackName: A\n0.5 a\n\
+                    \n# ptr: #0002\ntrackName: B\n0.5 b\n";
+        let mut document = parse_hkanno_document_str(text).unwrap();
+        let remap = Retime::Remap { from_duration: 2.0 };
+
+        assert!(document
+            .retime(remap, &AnnotationFilter::default())
+            .is_err());
+        assert_eq!(document, parse_hkanno_document_str(text).unwrap());
+    }
+}
@@ -0,0 +1,415 @@
+//! Annotations of many files as one CSV/TSV table, for review in a spreadsheet.
+//!
+//! [`export_table`] writes one row per annotation:
+//!
+//! ```txt
+//! file,hash,ptr,track,time,frame,text
+//! animations/attack.hkx,5d3a0c8e1b2f4a67,3,PairedRoot,0.1,3,MCO_DodgeOpen
+//! animations/attack.hkx,5d3a0c8e1b2f4a67,3,PairedRoot,0.45,,MCO_DodgeClose
+//! ```
+//!
+//! `frame` is the frame `time` falls on at the export's frame rate (empty between frames).
+//! `hash` fingerprints the file as it was exported, so [`import_table`] can refuse
+//! to overwrite a file that changed since.
+//!
+//! [`import_table`] treats the rows of a file as the complete set of its annotations:
+//! every annotation of a listed file without a row is removed. Tracks are matched by
+//! name; unknown names add a track and tracks without rows are kept empty. Files
+//! without any row are not touched.
+use rayon::prelude::*;
+use snafu::ResultExt as _;
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+pub use crate::hash::file_hash;
+
+use crate::{
+    editor::{apply_document_loaded, ApplyOptions, ApplyReport},
+    frame_at, frame_time, Annotation, AnnotationTrack, HkannoDocument, HkannoError, IoSnafu,
+    LoadedHkx,
+};
+
+/// Delimiter of an annotation table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TableFormat {
+    /// Comma-separated values.
+    #[default]
+    Csv,
+    /// Tab-separated values.
+    Tsv,
+}
+
+impl TableFormat {
+    /// Picks the format from a `.csv`, `.tsv` or `.tab` extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "csv" => Some(Self::Csv),
+            "tsv" | "tab" => Some(Self::Tsv),
+            _ => None,
+        }
+    }
+
+    const fn delimiter(self) -> u8 {
+        match self {
+            Self::Csv => b',',
+            Self::Tsv => b'\t',
+        }
+    }
+}
+
+/// One annotation of an annotation table.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TableRow {
+    /// HKX/XML file the annotation belongs to.
+    pub file: PathBuf,
+    /// [`file_hash`] of the file when it was exported.
+    pub hash: String,
+    /// Pointer of the animation (`0` for the only animation of the file).
+    pub ptr: usize,
+    /// Track name (empty for a track without name).
+    pub track: Option<String>,
+    /// Time in seconds. On import, may be left empty to use `frame` instead.
+    pub time: Option<f32>,
+    /// 0-based frame `time` falls on, empty if it lies between frames.
+    pub frame: Option<u32>,
+    /// Annotation text.
+    pub text: Option<String>,
+}
+
+/// Rows written per file by [`export_table`].
+#[derive(Debug, Default)]
+pub struct TableExport {
+    /// Each input file, in input order, with its row count or why it was left out.
+    pub files: Vec<(PathBuf, Result<usize, HkannoError>)>,
+}
+
+/// Writes the annotations of `files` to `writer` as a table, with frames at `fps`.
+///
+/// Files that cannot be read are left out and reported in [`TableExport::files`].
+///
+/// # Errors
+/// If writing to `writer` fails.
+pub fn export_table<W: io::Write>(
+    files: &[PathBuf],
+    fps: f32,
+    format: TableFormat,
+    writer: W,
+) -> Result<TableExport, HkannoError> {
+    let rows: Vec<_> = files.par_iter().map(|path| table_rows(path, fps)).collect();
+
+    let mut table = csv::WriterBuilder::new()
+        .delimiter(format.delimiter())
+        .from_writer(writer);
+    let mut export = TableExport::default();
+    for (path, rows) in files.iter().zip(rows) {
+        let count = match rows {
+            Ok(rows) => {
+                for row in &rows {
+                    table.serialize(row).map_err(table_error)?;
+                }
+                Ok(rows.len())
+            }
+            Err(err) => Err(err),
+        };
+        export.files.push((path.clone(), count));
+    }
+    table.flush().map_err(|err| HkannoError::AnnotationTable {
+        message: err.to_string(),
+    })?;
+    Ok(export)
+}
+
+/// Reads `path` and turns its annotations into rows.
+fn table_rows(path: &Path, fps: f32) -> Result<Vec<TableRow>, HkannoError> {
+    let bytes = std::fs::read(path).with_context(|_| IoSnafu { path })?;
+    let hash = file_hash(&bytes);
+    let document = LoadedHkx::from_bytes(bytes, path)?.extract(fps)?;
+
+    let mut rows = Vec::new();
+    for hkanno in document.animations {
+        for track in hkanno.annotation_tracks {
+            for annotation in track.annotations {
+                rows.push(TableRow {
+                    file: path.to_path_buf(),
+                    hash: hash.clone(),
+                    ptr: hkanno.ptr,
+                    track: track.track_name.as_deref().map(str::to_string),
+                    time: Some(annotation.time),
+                    frame: frame_at(annotation.time, fps),
+                    text: annotation.text.map(|text| text.into_owned()),
+                });
+            }
+        }
+    }
+    Ok(rows)
+}
+
+/// What [`import_table`] did with one file.
+#[derive(Debug)]
+pub struct TableImport {
+    pub path: PathBuf,
+    /// [`HkannoError::FileChangedSinceExport`] if the file changed since the export.
+    pub result: Result<ApplyReport, HkannoError>,
+}
+
+/// Reads a table and writes the annotations of each listed file into it, in place.
+///
+/// `fps` converts `frame` cells; `format` and `options` are as for
+/// [`apply_hkanno`](crate::editor::apply_hkanno). Annotations are sorted by time
+/// within each track.
+///
+/// A failing file does not stop the others; see [`TableImport::result`].
+///
+/// # Errors
+/// If the table cannot be read, or a row is invalid (nothing is written then).
+pub async fn import_table<R: io::Read>(
+    reader: R,
+    table_format: TableFormat,
+    fps: f32,
+    format: &str,
+    options: &ApplyOptions,
+) -> Result<Vec<TableImport>, HkannoError> {
+    let mut imports = Vec::new();
+    for (path, rows) in read_table(reader, table_format, fps)? {
+        let result = import_file(&path, rows, fps, format, options).await;
+        imports.push(TableImport { path, result });
+    }
+    Ok(imports)
+}
+
+/// A row with the line it was read from.
+type NumberedRow = (u64, TableRow);
+
+/// Reads every row, grouped by file in order of appearance.
+fn read_table<R: io::Read>(
+    reader: R,
+    format: TableFormat,
+    fps: f32,
+) -> Result<Vec<(PathBuf, Vec<NumberedRow>)>, HkannoError> {
+    let mut table = csv::ReaderBuilder::new()
+        .delimiter(format.delimiter())
+        .from_reader(reader);
+    let headers = table.headers().map_err(table_error)?.clone();
+
+    let mut files: Vec<(PathBuf, Vec<NumberedRow>)> = Vec::new();
+    let mut index = HashMap::new();
+    for record in table.records() {
+        let record = record.map_err(table_error)?;
+        let line = record.position().map_or(0, |position| position.line());
+        let row: TableRow = record.deserialize(Some(&headers)).map_err(table_error)?;
+        if row.time.is_none() && row.frame.is_none() {
+            return Err(invalid_row(line, "either `time` or `frame` is required"));
+        }
+        let multiline = |cell: &Option<String>| {
+            cell.as_deref()
+                .is_some_and(|cell| cell.contains(['\n', '\r']))
+        };
+        if multiline(&row.track) || multiline(&row.text) {
+            return Err(invalid_row(
+                line,
+                "`track` and `text` must not contain line breaks",
+            ));
+        }
+        if let (Some(time), Some(frame)) = (row.time, row.frame) {
+            if frame_at(time, fps) != Some(frame) {
+                return Err(invalid_row(
+                    line,
+                    format!("`time` {time} is not on `frame` {frame}; clear one of them"),
+                ));
+            }
+        }
+
+        let i = *index.entry(row.file.clone()).or_insert_with(|| {
+            files.push((row.file.clone(), Vec::new()));
+            files.len() - 1
+        });
+        files[i].1.push((line, row));
+    }
+    Ok(files)
+}
+
+/// Checks the file against the exported hash and writes its rows into it.
+async fn import_file(
+    path: &Path,
+    rows: Vec<NumberedRow>,
+    fps: f32,
+    format: &str,
+    options: &ApplyOptions,
+) -> Result<ApplyReport, HkannoError> {
+    let bytes = tokio::fs::read(path)
+        .await
+        .with_context(|_| IoSnafu { path })?;
+    let actual = file_hash(&bytes);
+    if let Some((_, row)) = rows.iter().find(|(_, row)| row.hash != actual) {
+        return Err(HkannoError::FileChangedSinceExport {
+            exported: row.hash.clone(),
+            actual,
+        });
+    }
+
+    let loaded = LoadedHkx::from_bytes(bytes, path)?;
+    let document = document_from_rows(loaded.extract(fps)?, rows, fps)?;
+    apply_document_loaded(&loaded, path, document, format, options).await
+}
+
+/// Replaces the annotations of `document` with `rows`.
+fn document_from_rows(
+    mut document: HkannoDocument<'static>,
+    rows: Vec<NumberedRow>,
+    fps: f32,
+) -> Result<HkannoDocument<'static>, HkannoError> {
+    for hkanno in &mut document.animations {
+        for track in &mut hkanno.annotation_tracks {
+            track.annotations.clear();
+            track.num_annotations = None;
+        }
+    }
+
+    let single = document.animations.len() == 1;
+    for (line, row) in rows {
+        let hkanno = document
+            .animations
+            .iter_mut()
+            .find(|hkanno| hkanno.ptr == row.ptr || (row.ptr == 0 && single))
+            .ok_or_else(|| invalid_row(line, format!("no animation `#{:04}`", row.ptr)))?;
+
+        let track_name = row.track.filter(|name| !name.is_empty());
+        let tracks = &mut hkanno.annotation_tracks;
+        let track = match tracks
+            .iter()
+            .position(|track| track.track_name.as_deref() == track_name.as_deref())
+        {
+            Some(i) => &mut tracks[i],
+            None => {
+                hkanno.num_annotation_tracks = None;
+                tracks.push(AnnotationTrack {
+                    track_name: track_name.map(Into::into),
+                    num_annotations: None,
+                    annotations: Vec::new(),
+                });
+                tracks.last_mut().expect("just pushed")
+            }
+        };
+
+        let time = match (row.time, row.frame) {
+            (Some(time), _) => time,
+            (None, Some(frame)) => frame_time(frame, fps),
+            (None, None) => unreachable!("checked while reading the table"),
+        };
+        track.annotations.push(Annotation {
+            time,
+            text: row.text.map(Into::into),
+        });
+    }
+
+    for hkanno in &mut document.animations {
+        for track in &mut hkanno.annotation_tracks {
+            track.annotations.sort_by(|a, b| a.time.total_cmp(&b.time));
+        }
+    }
+    Ok(document)
+}
+
+fn table_error(err: csv::Error) -> HkannoError {
+    HkannoError::AnnotationTable {
+        message: err.to_string(),
+    }
+}
+
+fn invalid_row(line: u64, message: impl Into<String>) -> HkannoError {
+    HkannoError::AnnotationTable {
+        message: format!("line {line}: {}", message.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hkanno;
+    use std::borrow::Cow;
+
+    #[test]
+    fn rows_replace_annotations_by_track() {
+        let tsv = "file\thash\tptr\ttrack\ttime\tframe\ttext
+a.hkx\t00\t0\tPairedRoot\t0.5\t\tSecond
+a.hkx\t00\t0\tPairedRoot\t\t3\tFirst
+b.hkx\t00\t7\t\t0\t0\tUnnamed
+a.hkx\t00\t0\tNew\t1\t30\t
+";
+        let files = read_table(tsv.as_bytes(), TableFormat::Tsv, 30.0).unwrap();
+        assert_eq!(files.len(), 2);
+        let (path, rows) = files.into_iter().next().unwrap();
+        assert_eq!(path, Path::new("a.hkx"));
+        assert_eq!(
+            rows.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+            [2, 3, 5]
+        );
+
+        let current = HkannoDocument::from(Hkanno {
+            ptr: 3,
+            num_original_frames: 38,
+            duration: 1.5,
+            num_annotation_tracks: None,
+            fps: None,
+            annotation_tracks: ["PairedRoot", "Foot_L"]
+                .map(|name| AnnotationTrack {
+                    track_name: Some(Cow::Borrowed(name)),
+                    num_annotations: None,
+                    annotations: vec![Annotation {
+                        time: 0.25,
+                        text: Some(Cow::Borrowed("MCO_Step")),
+                    }],
+                })
+                .to_vec(),
+        });
+        let tracks = document_from_rows(current, rows, 30.0)
+            .unwrap()
+            .animations
+            .remove(0)
+            .annotation_tracks;
+
+        let summary: Vec<_> = tracks
+            .iter()
+            .map(|track| {
+                let events: Vec<_> = track
+                    .annotations
+                    .iter()
+                    .map(|ann| (ann.time, ann.text.as_deref()))
+                    .collect();
+                (track.track_name.as_deref(), events)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    Some("PairedRoot"),
+                    vec![(0.1, Some("First")), (0.5, Some("Second"))]
+                ),
+                (Some("Foot_L"), vec![]),
+                (Some("New"), vec![(1.0, None)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_inconsistent_rows() {
+        let csv = "file,hash,ptr,track,time,frame,text\na.hkx,00,0,A,0.5,3,X\n";
+        let err = read_table(csv.as_bytes(), TableFormat::Csv, 30.0).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{err}");
+
+        let csv = "file,hash,ptr,track,time,frame,text\na.hkx,00,0,A,,,X\n";
+        assert!(read_table(csv.as_bytes(), TableFormat::Csv, 30.0).is_err());
+
+        let csv = "file,hash,ptr,track,time,frame,text\na.hkx,00,0,A,0.5,,X\na.hkx,00,0,A,1,,\"two\nlines\"\n";
+        let err = read_table(csv.as_bytes(), TableFormat::Csv, 30.0).unwrap_err();
+        assert!(
+            err.to_string().contains("line 3: `track` and `text`"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Duration and frame count of `hkaAnimation`-derived classes.
+//!
+//! Only `hkaSplineCompressedAnimation` stores its frame count; every other variant
+//! only knows its duration, so the frame count is derived from a frame rate.
+//! Skyrim animations are authored at [`DEFAULT_FPS`], other titles may use 60.
+use havok_classes::Classes;
+
+/// Frame rate used when none is given (Skyrim's 30 fps).
+pub const DEFAULT_FPS: f32 = 30.0;
+
+/// Duration and frame count of an animation.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnimationTiming {
+    /// Length in seconds.
+    pub duration: f32,
+    /// Number of sampled frames, counting both the first and the last frame.
+    pub num_frames: i32,
+    /// Whether `num_frames` is the stored count of an `hkaSplineCompressedAnimation`
+    /// rather than derived from the duration.
+    #[serde(skip)]
+    pub spline: bool,
+}
+
+impl AnimationTiming {
+    /// Timing of an animation lasting `duration` seconds, sampled at `fps`.
+    pub fn from_duration(duration: f32, fps: f32) -> Self {
+        Self {
+            duration,
+            num_frames: frame_count(duration, fps),
+            spline: false,
+        }
+    }
+
+    /// Reads the timing of an `hkaAnimation`-derived class.
+    ///
+    /// `hkaSplineCompressedAnimation` uses its stored `numFrames`; the other
+    /// variants derive it from the duration at `fps`.
+    ///
+    /// Earlier versions reported `numFrames + 1` for spline animations, so texts
+    /// they dumped declare one frame more; [`Hkanno::validate`](crate::Hkanno::validate)
+    /// accepts that value too.
+    ///
+    /// Returns `None` if `class` is not `hkaAnimation`-derived.
+    pub fn of(class: &Classes<'_>, fps: f32) -> Option<Self> {
+        let duration = match class {
+            Classes::hkaAnimation(class) => class.m_duration,
+            Classes::hkaDeltaCompressedAnimation(class) => class.parent.m_duration,
+            Classes::hkaInterleavedUncompressedAnimation(class) => class.parent.m_duration,
+            Classes::hkaQuantizedAnimation(class) => class.parent.m_duration,
+            Classes::hkaSplineCompressedAnimation(class) => {
+                return Some(Self {
+                    duration: class.parent.m_duration,
+                    num_frames: class.m_numFrames,
+                    spline: true,
+                });
+            }
+            Classes::hkaWaveletCompressedAnimation(class) => class.parent.m_duration,
+            _ => return None,
+        };
+        Some(Self::from_duration(duration, fps))
+    }
+}
+
+/// Whether `fps` is usable as a frame rate: finite and positive.
+pub fn is_valid_fps(fps: f32) -> bool {
+    fps.is_finite() && fps > 0.0
+}
+
+/// Largest distance (in frames) from a whole frame that still counts as "on" that frame.
+///
+/// Covers the `f32` error of `frame / fps` and the 6-decimal rounding of seconds in hkanno text.
+const FRAME_EPSILON: f32 = 1e-3;
+
+/// Time in seconds of the 0-based `frame` at `fps`.
+pub fn frame_time(frame: u32, fps: f32) -> f32 {
+    frame as f32 / fps
+}
+
+/// The 0-based frame `time` falls on at `fps`, or `None` if it lies between frames.
+pub fn frame_at(time: f32, fps: f32) -> Option<u32> {
+    let frame = time * fps;
+    let nearest = frame.round();
+    (nearest >= 0.0 && (frame - nearest).abs() <= FRAME_EPSILON).then_some(nearest as u32)
+}
+
+/// Number of frames sampled over `duration` seconds at `fps`, counting both ends.
+///
+/// The product is rounded, not truncated, so `f32` error such as
+/// `0.9999999 * 30.0 = 29.999998` still yields 31 frames.
+pub fn frame_count(duration: f32, fps: f32) -> i32 {
+    (duration * fps).round() as i32 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use havok_classes::{
+        hkaAnimation, hkaInterleavedUncompressedAnimation, hkaSplineCompressedAnimation,
+    };
+
+    #[test]
+    fn frame_count_rounds() {
+        assert_eq!(frame_count(1.0, DEFAULT_FPS), 31);
+        assert_eq!(frame_count(0.999_999_9, DEFAULT_FPS), 31);
+        assert_eq!(frame_count(1.5, 60.0), 91);
+        assert_eq!(frame_count(0.0, DEFAULT_FPS), 1);
+    }
+
+    #[test]
+    fn frame_time_round_trips() {
+        for fps in [DEFAULT_FPS, 60.0] {
+            for frame in 0..=600 {
+                assert_eq!(frame_at(frame_time(frame, fps), fps), Some(frame));
+            }
+        }
+        assert_eq!(frame_at(0.45, DEFAULT_FPS), None);
+        assert_eq!(frame_at(-1.0, DEFAULT_FPS), None);
+    }
+
+    #[test]
+    fn non_spline_uses_fps() {
+        let class = hkaInterleavedUncompressedAnimation {
+            parent: hkaAnimation {
+                m_duration: 2.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let class = Classes::hkaInterleavedUncompressedAnimation(class.into());
+
+        assert_eq!(
+            AnimationTiming::of(&class, DEFAULT_FPS),
+            Some(AnimationTiming {
+                duration: 2.0,
+                num_frames: 61,
+                spline: false,
+            })
+        );
+        assert_eq!(AnimationTiming::of(&class, 60.0).unwrap().num_frames, 121);
+    }
+
+    #[test]
+    fn spline_uses_stored_frame_count() {
+        let class = hkaSplineCompressedAnimation {
+            parent: hkaAnimation {
+                m_duration: 1.5,
+                ..Default::default()
+            },
+            m_numFrames: 38,
+            ..Default::default()
+        };
+        let class = Classes::hkaSplineCompressedAnimation(class.into());
+
+        let timing = AnimationTiming::of(&class, 60.0).unwrap();
+        assert_eq!(
+            timing,
+            AnimationTiming {
+                duration: 1.5,
+                num_frames: 38,
+                spline: true,
+            }
+        );
+
+        // Texts dumped by earlier releases declare `numFrames + 1`.
+        let declared = |num_original_frames| crate::Hkanno {
+            ptr: 0,
+            num_original_frames,
+            duration: 0.0,
+            num_annotation_tracks: None,
+            fps: None,
+            annotation_tracks: Vec::new(),
+        };
+        assert!(declared(38).validate(timing).is_empty());
+        assert!(declared(39).validate(timing).is_empty());
+        assert!(!declared(40).validate(timing).is_empty());
+
+        // Other classes never had the off-by-one count.
+        let interleaved = AnimationTiming::from_duration(1.5, DEFAULT_FPS);
+        assert!(declared(46).validate(interleaved).is_empty());
+        assert!(!declared(47).validate(interleaved).is_empty());
+    }
+}
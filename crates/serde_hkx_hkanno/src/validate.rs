@@ -0,0 +1,520 @@
+//! Semantic checks of an [`Hkanno`] against the animation it is written into.
+//!
+//! The text parser only enforces syntax. This module catches values that parse
+//! fine but are meaningless (or silently ignored) in game, such as events placed
+//! after the end of the animation.
+use havok_types::NULL_STR;
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use crate::{
+    AnimationTiming, AnnotationEvent, Hkanno, InstructionRegistry, PayloadError, PayloadPrefix,
+    DEFAULT_FPS,
+};
+
+/// Slack allowed when comparing times against the duration.
+///
+/// hkanno text stores times with 6 decimals, so a time rounded up on output may
+/// exceed the exact `f32` duration by up to half a unit in the last place.
+pub(crate) const TIME_EPSILON: f32 = 1e-6;
+
+/// How severe a [`ValidationIssue`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Severity {
+    /// Suspicious, but still written.
+    Warning,
+    /// The annotation cannot work as intended.
+    Error,
+}
+
+/// How [`Hkanno::update_hkx_bytes`](crate::Hkanno::update_hkx_bytes) treats validation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidationMode {
+    /// Do not validate.
+    Off,
+    /// Validate and log issues, but always write.
+    #[default]
+    Warn,
+    /// Validate and refuse to write if any [`Severity::Error`] issue is found.
+    Strict,
+}
+
+/// Everything [`Hkanno::update_hkx_bytes`](crate::Hkanno::update_hkx_bytes) needs to validate.
+#[derive(Debug, Clone)]
+pub struct ValidationOptions {
+    /// Whether to validate, and whether errors block writing.
+    pub mode: ValidationMode,
+    /// Frame rate used to derive the frame count of non-spline animations.
+    pub fps: f32,
+    /// Instructions that `PIE.@...` annotations are checked against; `None` skips the check.
+    pub pie: Option<Arc<InstructionRegistry>>,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        Self {
+            mode: ValidationMode::default(),
+            fps: DEFAULT_FPS,
+            pie: Some(InstructionRegistry::builtin()),
+        }
+    }
+}
+
+impl ValidationOptions {
+    /// Options that skip validation entirely.
+    pub const fn off() -> Self {
+        Self {
+            mode: ValidationMode::Off,
+            fps: DEFAULT_FPS,
+            pie: None,
+        }
+    }
+}
+
+/// A semantic problem found by [`Hkanno::validate`] or [`Hkanno::validate_payloads`].
+///
+/// `track` and `index` are 0-based indices into `annotation_tracks` and `annotations`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ValidationIssue {
+    /// Annotation time is NaN or infinite.
+    NonFiniteTime {
+        track: usize,
+        index: usize,
+        time: f32,
+    },
+    /// Annotation time is below zero.
+    NegativeTime {
+        track: usize,
+        index: usize,
+        time: f32,
+    },
+    /// Annotation time is after the end of the animation.
+    TimeBeyondDuration {
+        track: usize,
+        index: usize,
+        time: f32,
+        duration: f32,
+    },
+    /// Annotation time is earlier than the one before it in the same track.
+    NonMonotonicTime {
+        track: usize,
+        index: usize,
+        time: f32,
+        previous: f32,
+    },
+    /// Annotation text is empty.
+    EmptyText { track: usize, index: usize },
+    /// Annotation text contains a line break, so it cannot be written as hkanno text.
+    MultilineText { track: usize, index: usize },
+    /// Track name contains a line break, starts with whitespace or is the null
+    /// string, so it does not read back the same from a `trackName:` line.
+    InvalidTrackName { track: usize, name: String },
+    /// Another track already uses this name.
+    DuplicateTrackName {
+        track: usize,
+        first: usize,
+        name: String,
+    },
+    /// `# numAnnotations` disagrees with the actual annotation count.
+    AnnotationCountMismatch {
+        track: usize,
+        declared: usize,
+        actual: usize,
+    },
+    /// `# numAnnotationTracks` disagrees with the actual track count.
+    TrackCountMismatch { declared: usize, actual: usize },
+    /// `# duration` disagrees with the animation's duration.
+    DurationMismatch { declared: f32, actual: f32 },
+    /// `# numOriginalFrames` disagrees with the animation's frame count.
+    FrameCountMismatch { declared: i32, actual: i32 },
+    /// `PIE.@<name>` is not in the instruction registry.
+    UnknownPayloadInstruction {
+        track: usize,
+        index: usize,
+        name: String,
+    },
+    /// `PIE.@<name>|...` does not match its instruction definition.
+    InvalidPayload {
+        track: usize,
+        index: usize,
+        error: PayloadError,
+    },
+}
+
+impl ValidationIssue {
+    /// Returns how severe this issue is.
+    pub const fn severity(&self) -> Severity {
+        match self {
+            Self::NonFiniteTime { .. }
+            | Self::NegativeTime { .. }
+            | Self::TimeBeyondDuration { .. }
+            | Self::MultilineText { .. }
+            | Self::InvalidTrackName { .. }
+            | Self::InvalidPayload { .. } => Severity::Error,
+            Self::NonMonotonicTime { .. }
+            | Self::EmptyText { .. }
+            | Self::DuplicateTrackName { .. }
+            | Self::AnnotationCountMismatch { .. }
+            | Self::TrackCountMismatch { .. }
+            | Self::DurationMismatch { .. }
+            | Self::FrameCountMismatch { .. }
+            | Self::UnknownPayloadInstruction { .. } => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NonFiniteTime { track, index, time } => {
+                write!(f, "track[{track}][{index}]: time {time} is not finite")
+            }
+            Self::NegativeTime { track, index, time } => {
+                write!(f, "track[{track}][{index}]: time {time} is negative")
+            }
+            Self::TimeBeyondDuration {
+                track,
+                index,
+                time,
+                duration,
+            } => write!(
+                f,
+                "track[{track}][{index}]: time {time} is beyond the duration {duration}"
+            ),
+            Self::NonMonotonicTime {
+                track,
+                index,
+                time,
+                previous,
+            } => write!(
+                f,
+                "track[{track}][{index}]: time {time} is earlier than the previous {previous}"
+            ),
+            Self::EmptyText { track, index } => write!(f, "track[{track}][{index}]: empty text"),
+            Self::MultilineText { track, index } => {
+                write!(f, "track[{track}][{index}]: text contains a line break")
+            }
+            Self::InvalidTrackName { track, name } => write!(
+                f,
+                "track[{track}]: name {name:?} must not contain line breaks, start with whitespace or be `{NULL_STR}`"
+            ),
+            Self::DuplicateTrackName { track, first, name } => write!(
+                f,
+                "track[{track}]: name `{name}` is already used by track[{first}]"
+            ),
+            Self::AnnotationCountMismatch {
+                track,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "track[{track}]: `# numAnnotations: {declared}` but {actual} annotation(s) found"
+            ),
+            Self::TrackCountMismatch { declared, actual } => write!(
+                f,
+                "`# numAnnotationTracks: {declared}` but {actual} track(s) found"
+            ),
+            Self::DurationMismatch { declared, actual } => write!(
+                f,
+                "`# duration: {declared}` but the animation lasts {actual}"
+            ),
+            Self::FrameCountMismatch { declared, actual } => write!(
+                f,
+                "`# numOriginalFrames: {declared}` but the animation has {actual} frame(s)"
+            ),
+            Self::UnknownPayloadInstruction { track, index, name } => write!(
+                f,
+                "track[{track}][{index}]: unknown PIE instruction `{name}`"
+            ),
+            Self::InvalidPayload {
+                track,
+                index,
+                error,
+            } => write!(f, "track[{track}][{index}]: {error}"),
+        }
+    }
+}
+
+impl Hkanno<'_> {
+    /// Checks the annotations against the target animation's `timing`.
+    ///
+    /// Declared header values that were left at their defaults (`0`, `0.0`, `None`)
+    /// are treated as "not declared" and are not compared.
+    ///
+    /// Returns every issue found; an empty `Vec` means the annotations are valid.
+    pub fn validate(&self, timing: AnimationTiming) -> Vec<ValidationIssue> {
+        let AnimationTiming {
+            duration,
+            num_frames,
+            spline,
+        } = timing;
+        let mut issues = Vec::new();
+
+        if self.duration != 0.0 && (self.duration - duration).abs() > TIME_EPSILON {
+            issues.push(ValidationIssue::DurationMismatch {
+                declared: self.duration,
+                actual: duration,
+            });
+        }
+        // Texts dumped by earlier releases declare `numFrames + 1` for spline animations.
+        let legacy_frames = num_frames.checked_add(1).filter(|_| spline);
+        if self.num_original_frames != 0
+            && self.num_original_frames != num_frames
+            && Some(self.num_original_frames) != legacy_frames
+        {
+            issues.push(ValidationIssue::FrameCountMismatch {
+                declared: self.num_original_frames,
+                actual: num_frames,
+            });
+        }
+        if let Some(declared) = self.num_annotation_tracks {
+            let actual = self.annotation_tracks.len();
+            if declared != actual {
+                issues.push(ValidationIssue::TrackCountMismatch { declared, actual });
+            }
+        }
+
+        let mut seen_names = HashMap::new();
+        for (track_index, track) in self.annotation_tracks.iter().enumerate() {
+            if let Some(name) = track.track_name.as_deref() {
+                if name.contains(['\n', '\r'])
+                    || name.starts_with(char::is_whitespace)
+                    || name == NULL_STR
+                {
+                    issues.push(ValidationIssue::InvalidTrackName {
+                        track: track_index,
+                        name: name.to_string(),
+                    });
+                }
+                if let Some(&first) = seen_names.get(name) {
+                    issues.push(ValidationIssue::DuplicateTrackName {
+                        track: track_index,
+                        first,
+                        name: name.to_string(),
+                    });
+                } else {
+                    seen_names.insert(name, track_index);
+                }
+            }
+
+            if let Some(declared) = track.num_annotations {
+                let actual = track.annotations.len();
+                if declared != actual {
+                    issues.push(ValidationIssue::AnnotationCountMismatch {
+                        track: track_index,
+                        declared,
+                        actual,
+                    });
+                }
+            }
+
+            let mut previous: Option<f32> = None;
+            for (index, ann) in track.annotations.iter().enumerate() {
+                let (track, time) = (track_index, ann.time);
+
+                if !time.is_finite() {
+                    issues.push(ValidationIssue::NonFiniteTime { track, index, time });
+                } else {
+                    if time < 0.0 {
+                        issues.push(ValidationIssue::NegativeTime { track, index, time });
+                    } else if time > duration + TIME_EPSILON {
+                        issues.push(ValidationIssue::TimeBeyondDuration {
+                            track,
+                            index,
+                            time,
+                            duration,
+                        });
+                    }
+
+                    if let Some(previous) = previous.filter(|&previous| time < previous) {
+                        issues.push(ValidationIssue::NonMonotonicTime {
+                            track,
+                            index,
+                            time,
+                            previous,
+                        });
+                    }
+                    previous = Some(time);
+                }
+
+                match ann.text.as_deref() {
+                    Some("") => issues.push(ValidationIssue::EmptyText { track, index }),
+                    Some(text) if text.contains(['\n', '\r']) => {
+                        issues.push(ValidationIssue::MultilineText { track, index });
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Checks every `PIE.@<name>|...` annotation against `registry`.
+    ///
+    /// Custom (`$`) and async (`!`) instructions are user-defined and are not checked.
+    pub fn validate_payloads(&self, registry: &InstructionRegistry) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        for (track, annotations) in self.annotation_tracks.iter().enumerate() {
+            for (index, ann) in annotations.annotations.iter().enumerate() {
+                let AnnotationEvent::Payload(payload) = ann.event() else {
+                    continue;
+                };
+                if !payload.event.eq_ignore_ascii_case("PIE")
+                    || payload.prefix != PayloadPrefix::Native
+                {
+                    continue;
+                }
+
+                match registry.get(&payload.name) {
+                    None => issues.push(ValidationIssue::UnknownPayloadInstruction {
+                        track,
+                        index,
+                        name: payload.name.into_owned(),
+                    }),
+                    Some(def) => {
+                        if let Err(error) = def.check(&payload.params) {
+                            issues.push(ValidationIssue::InvalidPayload {
+                                track,
+                                index,
+                                error,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_hkanno_str;
+
+    #[test]
+    fn valid_annotations_have_no_issues() {
+        let hkanno =
+            parse_hkanno_str("# duration: 1.5\ntrackName: A\n# numAnnotations: 2\n0.0 a\n1.5 b\n")
+                .unwrap();
+        assert_eq!(
+            hkanno.validate(AnimationTiming::from_duration(1.5, DEFAULT_FPS)),
+            []
+        );
+    }
+
+    #[test]
+    fn reports_each_kind_of_issue() {
+        let hkanno = parse_hkanno_str(
+            "# numAnnotationTracks: 3\n\
+             trackName: A\n# numAnnotations: 5\n-0.1 neg\n2.0 late\n0.5 back\n0.6 \n0.7 x\n\
+             trackName: A\n0.0 dup\n",
+        )
+        .unwrap();
+        let issues = hkanno.validate(AnimationTiming::from_duration(1.0, DEFAULT_FPS));
+
+        assert_eq!(
+            issues,
+            [
+                ValidationIssue::TrackCountMismatch {
+                    declared: 3,
+                    actual: 2
+                },
+                ValidationIssue::NegativeTime {
+                    track: 0,
+                    index: 0,
+                    time: -0.1
+                },
+                ValidationIssue::TimeBeyondDuration {
+                    track: 0,
+                    index: 1,
+                    time: 2.0,
+                    duration: 1.0
+                },
+                ValidationIssue::NonMonotonicTime {
+                    track: 0,
+                    index: 2,
+                    time: 0.5,
+                    previous: 2.0
+                },
+                ValidationIssue::EmptyText { track: 0, index: 3 },
+                ValidationIssue::DuplicateTrackName {
+                    track: 1,
+                    first: 0,
+                    name: "A".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_what_hkanno_text_cannot_hold() {
+        let mut hkanno = parse_hkanno_str("trackName: A\n0.1 a\n0.2 b\ntrackName: B\n").unwrap();
+        hkanno.annotation_tracks[0].annotations[1].text = Some("b\r\nc".into());
+        hkanno.annotation_tracks[1].track_name = Some(" B".into());
+        // `:` and a leading `#` read back fine from a `trackName:` line.
+        for name in ["B\nC", NULL_STR, "Foo:Bar", "#1"] {
+            let mut track = hkanno.annotation_tracks[1].clone();
+            track.track_name = Some(name.into());
+            hkanno.annotation_tracks.push(track);
+        }
+
+        let issues = hkanno.validate(AnimationTiming::from_duration(1.0, DEFAULT_FPS));
+        assert_eq!(
+            issues,
+            [
+                ValidationIssue::MultilineText { track: 0, index: 1 },
+                ValidationIssue::InvalidTrackName {
+                    track: 1,
+                    name: " B".to_string()
+                },
+                ValidationIssue::InvalidTrackName {
+                    track: 2,
+                    name: "B\nC".to_string()
+                },
+                ValidationIssue::InvalidTrackName {
+                    track: 3,
+                    name: NULL_STR.to_string()
+                },
+            ]
+        );
+        assert!(issues
+            .iter()
+            .all(|issue| issue.severity() == Severity::Error));
+    }
+
+    #[test]
+    fn payloads_are_checked_against_registry() {
+        let hkanno = parse_hkanno_str(
+            "trackName: A\n\
+             0.1 PIE.@SGVF|fSpeed|1.5\n\
+             0.2 PIE.@SGVF|fSpeed|fast\n\
+             0.3 PIE.@NOPE|1\n\
+             0.4 PIE.$Custom|anything\n",
+        )
+        .unwrap();
+        let issues = hkanno.validate_payloads(&InstructionRegistry::builtin());
+
+        assert_eq!(issues.len(), 2);
+        assert!(matches!(
+            issues[0],
+            ValidationIssue::InvalidPayload { index: 1, .. }
+        ));
+        assert_eq!(issues[0].severity(), Severity::Error);
+        assert!(matches!(
+            issues[1],
+            ValidationIssue::UnknownPayloadInstruction { index: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn non_finite_time_is_error() {
+        let hkanno = parse_hkanno_str("trackName: A\nnan x\ninf y\n").unwrap();
+        let issues = hkanno.validate(AnimationTiming::from_duration(1.0, DEFAULT_FPS));
+
+        assert_eq!(issues.len(), 2);
+        assert!(issues.iter().all(|i| i.severity() == Severity::Error));
+    }
+}
@@ -0,0 +1,219 @@
+//! Verification of serialized output.
+//!
+//! After an update is serialized, the produced bytes are deserialized again and
+//! compared with what was meant to be written:
+//!
+//! - the re-extracted annotations must equal the requested hkanno sections, and
+//! - every class must equal the class of the input file under the same pointer,
+//!   ignoring annotation tracks.
+//!
+//! Classes are compared in file order with exact values, so verification is
+//! meant for saving in the input's own format: converting HKX to XML rounds
+//! floats to 6 decimals, and converting XML to HKX may renumber pointers.
+use havok_classes::{hkaAnnotationTrack, Classes};
+use havok_serde::HavokClass as _;
+use serde_hkx_features::ClassMap;
+use snafu::ResultExt as _;
+use std::{mem, path::Path};
+
+use crate::{
+    diff_documents, parse_hkanno_document_borrowed, HkannoDiff, HkannoDocument, HkannoError,
+    SerdeHkxFeatureSnafu, DEFAULT_FPS,
+};
+
+/// Checks that `output` holds exactly `expected` with the `requested` annotations.
+///
+/// `expected` is the class map of the input file, before the annotations were written.
+///
+/// # Errors
+/// [`HkannoError::OutputVerificationFailed`] naming the first class that diverged,
+/// [`HkannoError::OutputClassCountMismatch`], or a deserialization error.
+pub(crate) fn verify_output(
+    expected: &ClassMap<'_>,
+    output: &Vec<u8>,
+    requested: &HkannoDocument<'_>,
+    input: &Path,
+) -> Result<(), HkannoError> {
+    let mut text = String::new();
+    let actual: ClassMap<'_> = serde_hkx_features::serde::de::deserialize(output, &mut text, input)
+        .context(SerdeHkxFeatureSnafu)?;
+    compare_output(expected, &actual, requested)
+}
+
+/// The checks of [`verify_output`] on the already deserialized `actual` classes.
+///
+/// # Errors
+/// Same as [`verify_output`], except for deserialization.
+fn compare_output(
+    expected: &ClassMap<'_>,
+    actual: &ClassMap<'_>,
+    requested: &HkannoDocument<'_>,
+) -> Result<(), HkannoError> {
+    if actual.len() != expected.len() {
+        return Err(HkannoError::OutputClassCountMismatch {
+            expected: expected.len(),
+            actual: actual.len(),
+        });
+    }
+
+    let extracted = parse_hkanno_document_borrowed(actual.clone(), DEFAULT_FPS)?;
+    let diff = diff_documents(&extracted, requested)?;
+    if let Some(animation) = diff.animations.first() {
+        let ptr = animation.ptr;
+        return Err(HkannoError::OutputVerificationFailed {
+            ptr,
+            class: actual.get(&ptr).map_or_else(String::new, class_name),
+            reason: format!(
+                "annotations differ from the requested ones (`-` written, `+` requested):\n{}",
+                HkannoDiff {
+                    animations: vec![animation.clone()],
+                }
+            ),
+        });
+    }
+
+    for ((&expected_ptr, expected), (&ptr, actual)) in expected.iter().zip(actual) {
+        if ptr != expected_ptr {
+            return Err(HkannoError::OutputVerificationFailed {
+                ptr,
+                class: class_name(actual),
+                reason: format!("expected pointer #{expected_ptr:04}"),
+            });
+        }
+
+        let (mut expected, mut actual) = (expected.clone(), actual.clone());
+        if let (Some(expected), Some(actual)) = (
+            annotation_tracks_mut(&mut expected),
+            annotation_tracks_mut(&mut actual),
+        ) {
+            mem::take(expected);
+            mem::take(actual);
+        }
+
+        if expected != actual {
+            let (expected_name, actual_name) = (class_name(&expected), class_name(&actual));
+            return Err(HkannoError::OutputVerificationFailed {
+                ptr,
+                reason: if expected_name == actual_name {
+                    "fields differ from the input".to_string()
+                } else {
+                    format!("expected `{expected_name}`")
+                },
+                class: actual_name,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Returns the annotation tracks of an `hkaAnimation`-derived class.
+fn annotation_tracks_mut<'a, 'b>(
+    class: &'b mut Classes<'a>,
+) -> Option<&'b mut Vec<hkaAnnotationTrack<'a>>> {
+    Some(match class {
+        Classes::hkaAnimation(class) => &mut class.m_annotationTracks,
+        Classes::hkaDeltaCompressedAnimation(class) => &mut class.parent.m_annotationTracks,
+        Classes::hkaInterleavedUncompressedAnimation(class) => &mut class.parent.m_annotationTracks,
+        Classes::hkaQuantizedAnimation(class) => &mut class.parent.m_annotationTracks,
+        Classes::hkaSplineCompressedAnimation(class) => &mut class.parent.m_annotationTracks,
+        Classes::hkaWaveletCompressedAnimation(class) => &mut class.parent.m_annotationTracks,
+        _ => return None,
+    })
+}
+
+/// Havok class name of `class`, e.g. `hkaSplineCompressedAnimation`.
+fn class_name(class: &Classes<'_>) -> String {
+    class.name().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_hkanno_str;
+    use havok_classes::{hkaAnimation, hkaSplineCompressedAnimation};
+
+    fn class_map(duration: f32) -> ClassMap<'static> {
+        let spline = hkaSplineCompressedAnimation {
+            parent: hkaAnimation {
+                m_duration: duration,
+                ..Default::default()
+            },
+            m_numFrames: 31,
+            ..Default::default()
+        };
+        [(1, Classes::hkaSplineCompressedAnimation(spline.into()))]
+            .into_iter()
+            .collect()
+    }
+
+    fn requested() -> HkannoDocument<'static> {
+        parse_hkanno_str("# ptr: #0001\ntrackName: Root\n0.1 Hit\n")
+            .unwrap()
+            .into_static()
+            .into()
+    }
+
+    #[test]
+    fn output_with_requested_annotations_passes() {
+        let expected = class_map(1.0);
+        let mut actual = class_map(1.0);
+        requested().write_to_classmap(&mut actual).unwrap();
+
+        compare_output(&expected, &actual, &requested()).unwrap();
+    }
+
+    #[test]
+    fn diverging_output_fails() {
+        let expected = class_map(1.0);
+
+        let unannotated = class_map(1.0);
+        let err = compare_output(&expected, &unannotated, &requested()).unwrap_err();
+        assert!(matches!(
+            err,
+            HkannoError::OutputVerificationFailed { ptr: 1, ref class, .. }
+                if class == "hkaSplineCompressedAnimation"
+        ));
+
+        let mut other_duration = class_map(2.0);
+        requested().write_to_classmap(&mut other_duration).unwrap();
+        let err = compare_output(&expected, &other_duration, &requested()).unwrap_err();
+        assert!(matches!(
+            err,
+            HkannoError::OutputVerificationFailed { ref reason, .. }
+                if reason == "fields differ from the input"
+        ));
+
+        let err = compare_output(&expected, &ClassMap::default(), &requested()).unwrap_err();
+        assert!(matches!(
+            err,
+            HkannoError::OutputClassCountMismatch {
+                expected: 1,
+                actual: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn renumbered_pointers_fail() {
+        let expected = class_map(1.0);
+        let requested: HkannoDocument<'_> =
+            parse_hkanno_str("# ptr: #0002\ntrackName: Root\n0.1 Hit\n")
+                .unwrap()
+                .into();
+        let mut renumbered: ClassMap<'_> = class_map(1.0)
+            .into_values()
+            .map(|class| (2, class))
+            .collect();
+        requested
+            .clone()
+            .write_to_classmap(&mut renumbered)
+            .unwrap();
+
+        let err = compare_output(&expected, &renumbered, &requested).unwrap_err();
+        assert!(matches!(
+            err,
+            HkannoError::OutputVerificationFailed { ptr: 2, ref reason, .. }
+                if reason == "expected pointer #0001"
+        ));
+    }
+}